@@ -0,0 +1,279 @@
+use std::ops::Range;
+
+use codespan_reporting::diagnostic::Diagnostic;
+use codespan_reporting::files::SimpleFile;
+
+use crate::astnode::ASTNode;
+use crate::errors::{AsDiagnostic, CompileError};
+use crate::lexer::{Token, tokenize};
+use crate::opcode::Opcode;
+use crate::parser::{DIRECTIVES, Parser};
+use crate::syscalls::{SYSCALLS, is_syscall, murmur3_32};
+use crate::version::SbpfVersion;
+use crate::warnings::{CompileWarning, LintLevel};
+
+// Editor queries over a single file for `sbpf lsp`. Positions are byte
+// offsets into the source, converting them is up to the client
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    // a label in .text
+    Label,
+    // a label in .rodata
+    Data,
+    Constant,
+    Extern,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // the name where it is defined, without the colon of a label
+    pub span: Range<usize>,
+    // the offset of a label or the value of a constant
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Instruction,
+    Directive,
+    Register,
+    Syscall,
+    Symbol(SymbolKind),
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+}
+
+pub struct Analysis {
+    source: String,
+    version: SbpfVersion,
+    tokens: Vec<Token>,
+    symbols: Vec<Symbol>,
+    errors: Vec<CompileError>,
+    warnings: Vec<CompileWarning>,
+    // the span, offset and encoding of every instruction, only known once
+    // the whole file parses
+    instructions: Vec<(Range<usize>, u64, Vec<u8>)>,
+}
+
+impl Analysis {
+    pub fn new(source: &str, version: SbpfVersion) -> Self {
+        let file = SimpleFile::new(String::new(), source.to_string());
+        let (tokens, mut errors) = tokenize(source);
+        let mut parser = Parser::new(tokens.clone(), &file).with_version(version);
+        let mut warnings = Vec::new();
+        let mut instructions = Vec::new();
+        match parser.parse() {
+            Ok(result) => {
+                for node in result.code_section.get_nodes() {
+                    if let ASTNode::Instruction { instruction, offset } = node
+                        && let Some(bytes) = node.bytecode(version) {
+                        instructions.push((instruction.span.clone(), *offset, bytes));
+                    }
+                }
+                warnings = result.warnings;
+            }
+            Err(parse_errors) => errors.extend(parse_errors),
+        }
+        errors.sort_by_key(|error| error.span().start);
+        let symbols = collect_symbols(source, &tokens, &parser);
+        Self { source: source.to_string(), version, tokens, symbols, errors, warnings, instructions }
+    }
+
+    // Errors then warnings, lints are reported at their default level
+    pub fn diagnostics(&self) -> Vec<Diagnostic<()>> {
+        self.errors.iter().map(|error| error.to_diagnostic())
+            .chain(self.warnings.iter().map(|warning| warning.to_diagnostic(LintLevel::Warn)))
+            .collect()
+    }
+
+    // Every label, constant and extern in source order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // Markdown describing the token under the cursor
+    pub fn hover(&self, offset: usize) -> Option<String> {
+        match self.token_at(offset)? {
+            Token::Opcode(opcode, span) => {
+                let mnemonic = &self.source[span.clone()];
+                let mut text = format!("**{}** `{}`\n", mnemonic, opcode.description_for(self.version));
+                for form in opcode.operand_forms() {
+                    text += &format!("\n    {} {}", mnemonic, form);
+                }
+                if let Some((_, offset, bytes)) = self.instructions.iter().find(|(span, _, _)| span.contains(&offset)) {
+                    let bytes: Vec<_> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    text += &format!("\n\nat .text+0x{:x}, encoded as `{}`", offset, bytes.join(" "));
+                }
+                Some(text)
+            }
+            Token::Register(register, _) => Some(match register {
+                0 => "**r0** return value".to_string(),
+                1..=5 => format!("**r{}** argument {}, not preserved across calls", register, register),
+                6..=9 => format!("**r{}** preserved across calls", register),
+                10 => "**r10** frame pointer, read-only".to_string(),
+                _ => format!("**r{}** is not a register, expected r0 to r10", register),
+            }),
+            Token::Identifier(name, _) | Token::Label(name, _) => match self.symbol(name) {
+                Some(symbol) => Some(match symbol.kind {
+                    SymbolKind::Label | SymbolKind::Data => format!("**{}** label at {}", name, symbol.detail),
+                    SymbolKind::Constant => format!("**{}** constant {}", name, symbol.detail),
+                    SymbolKind::Extern => format!("**{}** external function", name),
+                }),
+                None if is_syscall(name) => Some(syscall_detail(name)),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn definition(&self, offset: usize) -> Option<Range<usize>> {
+        let name = self.name_at(offset)?;
+        self.symbol(name).map(|symbol| symbol.span.clone())
+    }
+
+    // Every use of the label or constant under the cursor, in source order
+    pub fn references(&self, offset: usize, include_declaration: bool) -> Vec<Range<usize>> {
+        let Some(symbol) = self.name_at(offset).and_then(|name| self.symbol(name)) else {
+            return Vec::new();
+        };
+        let mut references: Vec<_> = self.tokens.iter()
+            .filter_map(|token| match token {
+                Token::Identifier(name, span) if *name == symbol.name && *span != symbol.span => Some(span.clone()),
+                _ => None,
+            })
+            .collect();
+        if include_declaration {
+            references.push(symbol.span.clone());
+            references.sort_by_key(|span| span.start);
+        }
+        references
+    }
+
+    // What may be typed at the cursor, along with the span of the partial
+    // word the completion replaces. The client does the filtering
+    pub fn completions(&self, offset: usize) -> (Range<usize>, Vec<Completion>) {
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+        let line = &self.source[line_start..offset];
+        let word_start = line.rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |idx| idx + 1);
+        let replace = line_start + word_start..offset;
+        // a label may share its line with the statement
+        let statement = line.rsplit_once(':').map_or(line, |(_, rest)| rest).trim_start();
+
+        let mut completions = Vec::new();
+        let symbols = |kinds: &[SymbolKind]| self.symbols.iter()
+            .filter(|symbol| kinds.contains(&symbol.kind))
+            .map(|symbol| Completion { label: symbol.name.clone(), kind: CompletionKind::Symbol(symbol.kind), detail: symbol.detail.clone() })
+            .collect::<Vec<_>>();
+        match statement.split_once(char::is_whitespace) {
+            None => {
+                if !statement.starts_with('.') {
+                    completions.extend(Opcode::mnemonics().map(|mnemonic| Completion {
+                        label: mnemonic.to_string(),
+                        kind: CompletionKind::Instruction,
+                        detail: Opcode::from_str(mnemonic).map_or("", |opcode| opcode.description_for(self.version)).to_string(),
+                    }));
+                }
+                completions.extend(DIRECTIVES.iter().map(|directive| Completion {
+                    label: format!(".{}", directive),
+                    kind: CompletionKind::Directive,
+                    detail: String::new(),
+                }));
+            }
+            Some(("call", _)) => {
+                completions.extend(symbols(&[SymbolKind::Label, SymbolKind::Extern]));
+                completions.extend(SYSCALLS.iter().map(|syscall| Completion {
+                    label: syscall.to_string(),
+                    kind: CompletionKind::Syscall,
+                    detail: syscall_detail(syscall),
+                }));
+            }
+            Some((directive, _)) if directive.starts_with('.') => {
+                completions.extend(symbols(&[SymbolKind::Label, SymbolKind::Data, SymbolKind::Constant]));
+            }
+            Some(_) => {
+                completions.extend((0..=10).map(|register| Completion {
+                    label: format!("r{}", register),
+                    kind: CompletionKind::Register,
+                    detail: String::new(),
+                }));
+                completions.extend(symbols(&[SymbolKind::Label, SymbolKind::Data, SymbolKind::Constant]));
+            }
+        }
+        (replace, completions)
+    }
+
+    // The first definition wins, same as in the parser
+    fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    // The cursor may also sit right after the token
+    fn token_at(&self, offset: usize) -> Option<&Token> {
+        let tokens = || self.tokens.iter().filter(|token| !matches!(token, Token::Newline(_)));
+        tokens().find(|token| token.span().contains(&offset))
+            .or_else(|| tokens().find(|token| token.span().end == offset))
+    }
+
+    fn name_at(&self, offset: usize) -> Option<&str> {
+        match self.token_at(offset)? {
+            Token::Identifier(name, _) | Token::Label(name, _) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+fn syscall_detail(name: &str) -> String {
+    format!("syscall, hashed to 0x{:08x}", murmur3_32(name.as_bytes()))
+}
+
+fn collect_symbols(source: &str, tokens: &[Token], parser: &Parser) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut define = |symbol: Symbol| {
+        if !symbols.iter().any(|defined| defined.name == symbol.name) {
+            symbols.push(symbol);
+        }
+    };
+    for (idx, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Label(name, span) => {
+                let (kind, detail) = match parser.label_offset(name) {
+                    Some(offset) if offset < parser.m_accum_offset => (SymbolKind::Label, format!(".text+0x{:x}", offset)),
+                    Some(offset) => (SymbolKind::Data, format!(".rodata+0x{:x}", offset - parser.m_accum_offset)),
+                    None => (SymbolKind::Label, String::new()),
+                };
+                define(Symbol { name: name.clone(), kind, span: span.start..span.start + name.len(), detail });
+            }
+            Token::Directive(directive, _) if directive == "equ" => {
+                let Some(Token::Identifier(name, span)) = tokens.get(idx + 1) else { continue };
+                // the value as written, and evaluated unless it depends on labels
+                let end = tokens[idx..].iter().find(|token| matches!(token, Token::Newline(_) | Token::Comment(_, _)))
+                    .map_or(source.len(), |newline| newline.span().start);
+                let written = source[span.end..end].trim_start().trim_start_matches(',').trim();
+                let detail = match parser.constant(name) {
+                    Some(Token::ImmediateValue(value, _)) if value.to_i128().to_string() != written =>
+                        format!("= {} = {}", written, value.to_i128()),
+                    _ => format!("= {}", written),
+                };
+                define(Symbol { name: name.clone(), kind: SymbolKind::Constant, span: span.clone(), detail });
+            }
+            Token::Directive(directive, _) if directive == "extern" => {
+                for token in tokens[idx + 1..].iter().take_while(|token| !matches!(token, Token::Newline(_))) {
+                    if let Token::Identifier(name, span) = token {
+                        define(Symbol { name: name.clone(), kind: SymbolKind::Extern, span: span.clone(), detail: "extern".to_string() });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
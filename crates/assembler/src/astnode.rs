@@ -1,4 +1,6 @@
 use crate::opcode::Opcode;
+use crate::syscalls::murmur3_32;
+use crate::version::SbpfVersion;
 use crate::lexer::{Token, ImmediateValue};
 use crate::dynsym::RelocationType;
 use crate::debuginfo::{DebugInfo, RegisterHint, RegisterType};
@@ -25,6 +27,12 @@ pub enum ASTNode {
     RodataDecl {
         rodata_decl: RodataDecl,
     },
+    TypeDecl {
+        type_decl: TypeDecl,
+    },
+    SizeDecl {
+        size_decl: SizeDecl,
+    },
     Label {
         label: Label,
     },
@@ -33,6 +41,10 @@ pub enum ASTNode {
         rodata: ROData,
         offset: u64,
     },
+    Align {
+        align: Align,
+        offset: u64,
+    },
     Instruction {
         instruction: Instruction,
         offset: u64,
@@ -89,6 +101,37 @@ pub struct RodataDecl {
     pub span: Range<usize>,
 }
 
+#[derive(Debug, Clone)]
+pub struct TypeDecl {
+    pub name: String,
+    pub span: Range<usize>,
+}
+
+impl TypeDecl {
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SizeDecl {
+    pub name: String,
+    pub size: Token,
+    pub span: Range<usize>,
+}
+
+impl SizeDecl {
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    pub fn get_size(&self) -> u64 {
+        match &self.size {
+            Token::ImmediateValue(value, _) => value.to_i64() as u64,
+            _ => panic!("Invalid Size declaration"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Label {
     pub name: String,
@@ -97,20 +140,102 @@ pub struct Label {
 
 #[derive(Debug, Clone)]
 pub struct ROData {
-    pub name: String,
+    pub name: Option<String>,
     pub args: Vec<Token>,
     pub span: Range<usize>,
 }
 
 impl ROData {
+    // Size of each value of an integer data directive
+    pub fn value_size(directive: &str) -> Option<u64> {
+        match directive {
+            "byte" => Some(1),
+            "2byte" | "short" => Some(2),
+            "4byte" | "word" | "long" => Some(4),
+            "8byte" | "quad" => Some(8),
+            _ => None,
+        }
+    }
+    pub fn is_data_directive(directive: &str) -> bool {
+        Self::value_size(directive).is_some()
+            || matches!(directive, "ascii" | "asciz" | "string" | "zero" | "space" | "fill")
+    }
+    pub fn get_directive(&self) -> &str {
+        match &self.args[0] {
+            Token::Directive(name, _) => name,
+            _ => panic!("Invalid rodata declaration"),
+        }
+    }
+    fn get_int(&self, idx: usize) -> i64 {
+        match &self.args[idx] {
+            Token::ImmediateValue(value, _) => value.to_i64(),
+            _ => 0,
+        }
+    }
     pub fn get_size(&self) -> u64 {
-        let mut size = 0;
-        for arg in &self.args {
-            if let Token::StringLiteral(s, _) = arg {
-                size += s.len() as u64;
+        let values = &self.args[1..];
+        match self.get_directive() {
+            "ascii" | "asciz" | "string" => values.iter()
+                .map(|arg| match arg {
+                    Token::StringLiteral(s, _) => s.len() as u64,
+                    _ => 0,
+                })
+                .sum::<u64>() + if self.get_directive() == "ascii" { 0 } else { values.len() as u64 },
+            "zero" | "space" => self.get_int(1) as u64,
+            "fill" => self.get_int(1) as u64 * self.get_int(2) as u64,
+            directive => Self::value_size(directive).unwrap_or(0) * values.len() as u64,
+        }
+    }
+    pub fn bytecode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self.get_directive() {
+            "ascii" | "asciz" | "string" => {
+                for arg in &self.args[1..] {
+                    if let Token::StringLiteral(s, _) = arg {
+                        bytes.extend(s);
+                        if self.get_directive() != "ascii" {
+                            bytes.push(0);
+                        }
+                    }
+                }
+            }
+            "zero" => bytes.resize(self.get_int(1) as usize, 0),
+            "space" => bytes.resize(self.get_int(1) as usize, self.get_int(2) as u8),
+            "fill" => {
+                let size = self.get_int(2) as usize;
+                let value = self.get_int(3).to_le_bytes();
+                for _ in 0..self.get_int(1) {
+                    bytes.extend_from_slice(&value[..size]);
+                }
+            }
+            directive => {
+                let size = Self::value_size(directive).unwrap_or(0) as usize;
+                for idx in 1..self.args.len() {
+                    bytes.extend_from_slice(&self.get_int(idx).to_le_bytes()[..size]);
+                }
             }
         }
-        size
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Align {
+    pub directive: String,
+    // in bytes, `.p2align` is converted on parsing
+    pub alignment: u64,
+    // .text is padded with `ja +0` since the verifier rejects invalid opcodes
+    pub fill: Option<u8>,
+    pub padding: u64,
+    pub span: Range<usize>,
+}
+
+impl Align {
+    pub fn bytecode(&self) -> Vec<u8> {
+        match self.fill {
+            Some(fill) => vec![fill; self.padding as usize],
+            None => [Opcode::Ja.to_bytecode(), 0, 0, 0, 0, 0, 0, 0].repeat(self.padding as usize / 8),
+        }
     }
 }
 
@@ -131,12 +256,7 @@ impl Instruction {
     pub fn needs_relocation(&self) -> bool {
         match self.opcode {
             Opcode::Call => true,
-            Opcode::Lddw => {
-                match &self.operands[1] {
-                    Token::Identifier(_, _) => true,
-                    _ => false,
-                }
-            },
+            Opcode::Lddw => matches!(&self.operands[1], Token::Identifier(_, _)),
             _ => false,
         }
     }
@@ -161,9 +281,20 @@ impl Instruction {
 
 
 impl ASTNode {
-    pub fn bytecode_with_debug_map(&self, file: Option<&SimpleFile<String, String>>) -> Option<(Vec<u8>, HashMap<u64, DebugInfo>)> {
+    pub fn bytecode_with_debug_map(&self, file: Option<&SimpleFile<String, String>>, version: SbpfVersion) -> Option<(Vec<u8>, HashMap<u64, DebugInfo>)> {
         match self {
             ASTNode::Instruction { instruction: Instruction { opcode, operands, span }, offset } => {
+                // v2 computes `imm - dst` for sub with an immediate, adding the
+                // negated immediate keeps the meaning of `sub dst, imm`
+                let negated;
+                let (opcode, operands) = match (opcode, &operands[..]) {
+                    (Opcode::Sub32Imm | Opcode::Sub64Imm, [reg, Token::ImmediateValue(imm, span)]) if version.swap_sub_reg_imm_operands() => {
+                        let imm = ImmediateValue::Int((imm.to_i128() as i64).wrapping_neg());
+                        negated = vec![reg.clone(), Token::ImmediateValue(imm, span.clone())];
+                        (if *opcode == Opcode::Sub32Imm { &Opcode::Add32Imm } else { &Opcode::Add64Imm }, &negated)
+                    }
+                    _ => (opcode, operands),
+                };
                 let mut bytes = Vec::new();
                 let mut line_map = HashMap::new();
                 let mut debug_map = HashMap::new();
@@ -175,33 +306,64 @@ impl ASTNode {
                 };
                 line_map.insert(*offset, line_number);
                 let mut debug_info = DebugInfo::new(line_number);
-                bytes.push(opcode.to_bytecode());  // 1 byte opcode
+                bytes.push(opcode.to_bytecode_for(version));  // 1 byte opcode
                 
                 if *opcode == Opcode::Call {
-                    // currently hardcoded to call sol_log_
-                    bytes.extend_from_slice(&[0x10, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
-                } else if *opcode == Opcode::Lddw {
                     match &operands[..] {
-                        [Token::Register(reg, _), Token::ImmediateValue(imm, _)] => {
-                            // 1 byte register number (strip 'r' prefix)
-                            bytes.push(*reg);
-                            
-                            // 2 bytes of zeros (offset/reserved)
-                            bytes.extend_from_slice(&[0, 0]);
-
-                            // 8 bytes immediate value in little-endian
-                            let imm64 = match imm {
-                                ImmediateValue::Int(val) => *val as i64,
-                                ImmediateValue::Addr(val) => *val as i64,
+                        // Internal call, src = 1 and a pc-relative offset to the callee
+                        [Token::ImmediateValue(imm, _)] => {
+                            bytes.extend_from_slice(&[0x10, 0, 0]);
+                            let imm32 = match imm {
+                                ImmediateValue::Int(val) => *val as i32,
+                                ImmediateValue::UInt(val) => *val as i32,
+                                ImmediateValue::Addr(val) => *val as i32,
                             };
-                            bytes.extend_from_slice(&imm64.to_le_bytes()[..4]);
-                            bytes.extend_from_slice(&[0, 0, 0, 0]);
-                            bytes.extend_from_slice(&imm64.to_le_bytes()[4..8]);
+                            bytes.extend_from_slice(&imm32.to_le_bytes());
                         }
-                        _ => {}
+                        // Static syscall, src = 0 and the hash of its name
+                        [Token::Identifier(name, _)] if version.static_syscalls() => {
+                            bytes.extend_from_slice(&[0, 0, 0]);
+                            bytes.extend_from_slice(&murmur3_32(name.as_bytes()).to_le_bytes());
+                        }
+                        // Syscall, the loader patches in the hash through the R_BPF_64_32 relocation
+                        _ => {
+                            bytes.extend_from_slice(&[0x10, 0, 0]);
+                            bytes.extend_from_slice(&(-1i32).to_le_bytes());
+                        }
+                    }
+                } else if *opcode == Opcode::Callx {
+                    if let [Token::Register(reg, _)] = &operands[..] {
+                        if version.callx_uses_src_reg() {
+                            bytes.extend_from_slice(&[*reg << 4, 0, 0, 0, 0, 0, 0]);
+                        } else {
+                            bytes.extend_from_slice(&[0, 0, 0]);
+                            bytes.extend_from_slice(&(*reg as i32).to_le_bytes());
+                        }
+                    }
+                } else if *opcode == Opcode::Lddw {
+                    if let [Token::Register(reg, _), Token::ImmediateValue(imm, _)] = &operands[..] {
+                        // 1 byte register number (strip 'r' prefix)
+                        bytes.push(*reg);
+                        
+                        // 2 bytes of zeros (offset/reserved)
+                        bytes.extend_from_slice(&[0, 0]);
+
+                        // 8 bytes immediate value in little-endian
+                        let imm64 = match imm {
+                            ImmediateValue::Int(val) => *val,
+                            ImmediateValue::UInt(val) => *val as i64,
+                            ImmediateValue::Addr(val) => *val,
+                        };
+                        bytes.extend_from_slice(&imm64.to_le_bytes()[..4]);
+                        bytes.extend_from_slice(&[0, 0, 0, 0]);
+                        bytes.extend_from_slice(&imm64.to_le_bytes()[4..8]);
                     }
                 } else {
                     match &operands[..] {
+                        [Token::Register(reg, _)] => {
+                            bytes.push(*reg);
+                        },
+
                         [Token::ImmediateValue(imm, _)] => {
                             // 1 byte of zeros (no register)
                             bytes.push(0);
@@ -210,6 +372,7 @@ impl ASTNode {
                                 // 2 bytes immediate value in little-endian for 'ja'
                                 let imm16 = match imm {
                                     ImmediateValue::Int(val) => *val as i16,
+                                    ImmediateValue::UInt(val) => *val as i16,
                                     ImmediateValue::Addr(val) => *val as i16,
                                 };
                                 bytes.extend_from_slice(&imm16.to_le_bytes());
@@ -217,6 +380,7 @@ impl ASTNode {
                                 // 4 bytes immediate value in little-endian
                                 let imm32 = match imm {
                                     ImmediateValue::Int(val) => *val as i32,
+                                    ImmediateValue::UInt(val) => *val as i32,
                                     ImmediateValue::Addr(val) => *val as i32,
                                 };
                                 bytes.extend_from_slice(&imm32.to_le_bytes());
@@ -233,6 +397,7 @@ impl ASTNode {
                             // 4 bytes immediate value in little-endian
                             let imm32 = match imm {
                                 ImmediateValue::Int(val) => *val as i32,
+                                ImmediateValue::UInt(val) => *val as i32,
                                 ImmediateValue::Addr(val) => {
                                     debug_info.register_hint = RegisterHint {
                                         register: *reg as usize,
@@ -251,6 +416,7 @@ impl ASTNode {
                             // 2 bytes of offset in little-endian
                             let offset16 = match offset {
                                 ImmediateValue::Int(val) => *val as u16,
+                                ImmediateValue::UInt(val) => *val as u16,
                                 ImmediateValue::Addr(val) => *val as u16,
                             };
                             bytes.extend_from_slice(&offset16.to_le_bytes());
@@ -258,6 +424,7 @@ impl ASTNode {
                             // 4 bytes immediate value in little-endianß
                             let imm32 = match imm {
                                 ImmediateValue::Int(val) => *val as i32,
+                                ImmediateValue::UInt(val) => *val as i32,
                                 ImmediateValue::Addr(val) => {
                                     debug_info.register_hint = RegisterHint {
                                         register: *reg as usize,
@@ -286,6 +453,7 @@ impl ASTNode {
                             // Add the offset as a 16-bit value in little-endian
                             let offset16 = match offset {
                                 ImmediateValue::Int(val) => *val as u16,
+                                ImmediateValue::UInt(val) => *val as u16,
                                 ImmediateValue::Addr(val) => *val as u16,
                             };
                             bytes.extend_from_slice(&offset16.to_le_bytes());
@@ -298,6 +466,7 @@ impl ASTNode {
                             // Add the offset as a 16-bit value in little-endian
                             let offset16 = match offset {
                                 ImmediateValue::Int(val) => *val as u16,
+                                ImmediateValue::UInt(val) => *val as u16,
                                 ImmediateValue::Addr(val) => *val as u16,
                             };
                             bytes.extend_from_slice(&offset16.to_le_bytes());
@@ -317,24 +486,14 @@ impl ASTNode {
                 
                 Some((bytes, debug_map))
             },
-            ASTNode::ROData { rodata: ROData { name: _, args, .. }, .. } => {
-                let mut bytes = Vec::new();
-                let debug_map = HashMap::<u64, DebugInfo>::new();
-                for arg in args {
-                    if let Token::StringLiteral(s, _) = arg {
-                        // Convert string to bytes and add null terminator
-                        let str_bytes = s.as_bytes().to_vec();
-                        bytes.extend(str_bytes);
-                    }
-                }
-                Some((bytes, debug_map))
-            },
+            ASTNode::ROData { rodata, .. } => Some((rodata.bytecode(), HashMap::new())),
+            ASTNode::Align { align, .. } => Some((align.bytecode(), HashMap::new())),
             _ => None
         }
     }
 
     // Keep the old bytecode method for backward compatibility
-    pub fn bytecode(&self) -> Option<Vec<u8>> {
-        self.bytecode_with_debug_map(None).map(|(bytes, _)| bytes)
+    pub fn bytecode(&self, version: SbpfVersion) -> Option<Vec<u8>> {
+        self.bytecode_with_debug_map(None, version).map(|(bytes, _)| bytes)
     }
 }
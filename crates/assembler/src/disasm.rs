@@ -0,0 +1,524 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::{Error, Result};
+
+use crate::dynsym::RelocationType;
+use crate::elf::{ElfFile, ElfSection, ElfSymbol};
+use crate::format::format;
+use crate::header::ProgramHeader;
+use crate::opcode::Opcode;
+use crate::syscalls::{SYSCALLS, is_syscall, murmur3_32};
+use crate::version::SbpfVersion;
+
+// Turns a program back into source that `sbpf build` assembles to the same
+// file. Jump and call targets and the addresses loaded with lddw get a label,
+// named after the symbol there if any, and .rodata is split at its labels and
+// dumped as data directives. Encodings the assembler can't produce are still
+// decoded, and marked
+const UNSUPPORTED: &str = "# not supported by the assembler";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Location {
+    Text(u64),
+    Rodata(u64),
+}
+
+struct Instruction {
+    offset: u64,
+    code: u8,
+    opcode: Option<Opcode>,
+    dst: u8,
+    src: u8,
+    off: i16,
+    // sign extended, the full 64 bits for lddw
+    imm: i64,
+}
+
+impl Instruction {
+    fn size(&self) -> u64 {
+        if self.opcode == Some(Opcode::Lddw) { 16 } else { 8 }
+    }
+
+    // jumps and internal calls count in instructions from the next one
+    fn target(&self, delta: i64) -> Option<u64> {
+        u64::try_from(self.offset as i64 + (delta + 1) * 8).ok()
+    }
+}
+
+struct Disassembler<'a> {
+    version: SbpfVersion,
+    text: &'a ElfSection,
+    rodata: Option<&'a ElfSection>,
+    rodata_bytes: &'a [u8],
+    instructions: Vec<Instruction>,
+    boundaries: BTreeSet<u64>,
+    // dynamic relocations by the location they patch, with the name of
+    // their symbol
+    relocations: BTreeMap<Location, (u32, String)>,
+    // symbol names, the first one for each location
+    symbols: BTreeMap<Location, String>,
+    labels: BTreeMap<Location, String>,
+    names: HashSet<String>,
+}
+
+pub fn disassemble(bytes: &[u8]) -> Result<String> {
+    let elf = ElfFile::parse(bytes)?;
+    let version = SbpfVersion::from_e_flags(elf.e_flags)
+        .ok_or_else(|| Error::msg(format!("Unknown SBPF version in e_flags 0x{:x}", elf.e_flags)))?;
+    let text = elf.section(".text").ok_or_else(|| Error::msg("No .text section"))?;
+    let rodata = elf.section(".rodata");
+    let dynsym = elf.symbols(".dynsym")?;
+    let symtab = elf.symbols(".symtab")?;
+
+    let mut disassembler = Disassembler {
+        version,
+        text,
+        rodata,
+        rodata_bytes: match rodata {
+            Some(rodata) => elf.data(rodata)?,
+            None => &[],
+        },
+        instructions: decode(elf.data(text)?, version),
+        boundaries: BTreeSet::new(),
+        relocations: BTreeMap::new(),
+        symbols: BTreeMap::new(),
+        labels: BTreeMap::new(),
+        names: HashSet::new(),
+    };
+    disassembler.boundaries = disassembler.instructions.iter().map(|instruction| instruction.offset).collect();
+    for relocation in elf.relocations()? {
+        if let Some(location) = disassembler.locate(relocation.offset, false) {
+            let name = dynsym.get(relocation.symbol as usize).map(|symbol| symbol.name.clone()).unwrap_or_default();
+            disassembler.relocations.insert(location, (relocation.rel_type, name));
+        }
+    }
+    for symbol in symtab.iter().chain(dynsym.iter()) {
+        if symbol.shndx != 0 && is_identifier(&symbol.name)
+            && let Some(location) = disassembler.locate(symbol.value, false) {
+            disassembler.symbols.entry(location).or_insert_with(|| symbol.name.clone());
+        }
+    }
+
+    let mut header = Vec::new();
+    if version != SbpfVersion::default() {
+        header.push(format!("# SBPF {}, assemble with `sbpf build --arch {}`", version, version));
+    }
+
+    // The first global decides the entry point. Without symbol tables the
+    // name is lost, and with them a missing entry symbol means there was
+    // no `.globl`
+    let entry_offset = elf.e_entry.saturating_sub(text.addr);
+    let entry_symbol = dynsym.iter().chain(symtab.iter())
+        .find(|symbol| symbol.shndx != 0 && symbol.is_global() && symbol.value == elf.e_entry && is_identifier(&symbol.name))
+        .map(|symbol| symbol.name.clone());
+    let entry = match entry_symbol {
+        Some(name) => Some(name),
+        None if elf.section(".dynsym").is_none() && elf.section(".symtab").is_none() => Some("entrypoint".to_string()),
+        None => None,
+    };
+    if let Some(entry) = &entry {
+        disassembler.symbols.insert(Location::Text(entry_offset), entry.clone());
+        disassembler.label(Location::Text(entry_offset), "function");
+        header.push(format!(".globl {}", entry));
+    }
+
+    // Functions keep their symbols, the size is only written out when it
+    // isn't the distance to the next function or the entry point
+    let functions = functions(&symtab, text);
+    for (idx, function) in functions.iter().enumerate() {
+        let offset = function.value - text.addr;
+        if disassembler.symbols.get(&Location::Text(offset)) != Some(&function.name) {
+            continue;
+        }
+        disassembler.label(Location::Text(offset), "function");
+        if function.is_global() && Some(&function.name) != entry.as_ref() {
+            header.push(format!(".globl {}", function.name));
+        }
+        header.push(format!(".type {}, @function", function.name));
+        let end = functions[idx + 1..].iter().map(|next| next.value)
+            .chain(entry.is_some().then_some(elf.e_entry))
+            .filter(|start| *start > function.value)
+            .min()
+            .unwrap_or(text.addr.saturating_add(text.size));
+        if function.size != end - function.value {
+            header.push(format!(".size {}, {}", function.name, function.size));
+        }
+    }
+
+    disassembler.collect_labels();
+    let mut externs: Vec<_> = disassembler.relocations.values()
+        .filter(|(rel_type, name)| *rel_type == RelocationType::RSbfSyscall as u32 && !is_syscall(name) && is_identifier(name))
+        .map(|(_, name)| name.clone())
+        .collect();
+    externs.sort();
+    externs.dedup();
+    header.extend(externs.iter().map(|name| format!(".extern {}", name)));
+
+    let mut lines = header;
+    lines.push(String::new());
+    // .text starts 4 byte aligned unless some `.align` asked for more
+    if text.addralign > 4 {
+        lines.push(format!(".align {}", text.addralign));
+    }
+    lines.extend(disassembler.text_lines());
+    if let Some(rodata) = rodata {
+        lines.push(String::new());
+        lines.push(".rodata".to_string());
+        if rodata.addralign > 1 {
+            lines.push(format!(".align {}", rodata.addralign));
+        }
+        lines.extend(disassembler.rodata_lines());
+    }
+    let source = lines.join("\n") + "\n";
+    // lines the assembler can't read are left as they are
+    Ok(format(&source).unwrap_or(source))
+}
+
+fn decode(bytes: &[u8], version: SbpfVersion) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= bytes.len() {
+        let slot = &bytes[offset..offset + 8];
+        let mut opcode = Opcode::from_bytecode_for(slot[0], version);
+        let mut imm = i32::from_le_bytes(slot[4..8].try_into().unwrap()) as i64;
+        if opcode == Some(Opcode::Lddw) {
+            match bytes.get(offset + 12..offset + 16) {
+                Some(high) => imm = (imm as u32 as u64 | (u32::from_le_bytes(high.try_into().unwrap()) as u64) << 32) as i64,
+                // the second slot is missing
+                None => opcode = None,
+            }
+        }
+        let instruction = Instruction {
+            offset: offset as u64,
+            code: slot[0],
+            opcode,
+            dst: slot[1] & 0xf,
+            src: slot[1] >> 4,
+            off: i16::from_le_bytes(slot[2..4].try_into().unwrap()),
+            imm,
+        };
+        offset += instruction.size() as usize;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// The functions of .symtab with names the assembler accepts, by address
+fn functions<'a>(symtab: &'a [ElfSymbol], text: &ElfSection) -> Vec<&'a ElfSymbol> {
+    let mut functions: Vec<_> = symtab.iter()
+        .filter(|symbol| symbol.is_function() && symbol.shndx != 0 && is_identifier(&symbol.name))
+        .filter(|symbol| (text.addr..text.addr.saturating_add(text.size)).contains(&symbol.value))
+        .collect();
+    functions.sort_by_key(|symbol| symbol.value);
+    functions
+}
+
+// Names the lexer reads as an identifier, not a register or a mnemonic
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !(name.starts_with('r') && name[1..].chars().all(|c| c.is_ascii_digit()))
+        && Opcode::from_str(name).is_err()
+}
+
+impl Disassembler<'_> {
+    // Relocations and symbols hold addresses, .rodata may be labeled right
+    // past its end
+    fn locate(&self, address: u64, label_end: bool) -> Option<Location> {
+        if (self.text.addr..self.text.addr.saturating_add(self.text.size)).contains(&address) {
+            return Some(Location::Text(address - self.text.addr));
+        }
+        let rodata = self.rodata?;
+        let end = rodata.addr.saturating_add(rodata.size).saturating_add(label_end as u64);
+        (rodata.addr..end).contains(&address).then(|| Location::Rodata(address - rodata.addr))
+    }
+
+    // The label at `location`, defined if needed. Labels go where a symbol
+    // names the location or get a generated name
+    fn label(&mut self, location: Location, kind: &str) -> String {
+        if let Some(label) = self.labels.get(&location) {
+            return label.clone();
+        }
+        let name = match (self.symbols.get(&location), location) {
+            (Some(name), _) if !self.names.contains(name) => name.clone(),
+            (_, Location::Text(offset)) => format!("{}_{:x}", kind, offset),
+            (_, Location::Rodata(offset)) => format!("data_{:x}", offset),
+        };
+        self.names.insert(name.clone());
+        self.labels.insert(location, name.clone());
+        name
+    }
+
+    // Function labels are defined first so a function that's also jumped
+    // to keeps its name
+    fn collect_labels(&mut self) {
+        let mut functions = Vec::new();
+        let mut jumps = Vec::new();
+        for instruction in &self.instructions {
+            match instruction.opcode {
+                Some(Opcode::Call) if self.call_target(instruction).is_some() => {
+                    functions.push(Location::Text(self.call_target(instruction).unwrap()));
+                }
+                Some(Opcode::Lddw) => {
+                    if let Some(location) = self.address(instruction) {
+                        functions.push(self.containing(location).0);
+                    }
+                }
+                Some(opcode) if opcode.is_jump() => {
+                    if let Some(target) = self.jump_target(instruction) {
+                        jumps.push(Location::Text(target));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (location, relocation) in &self.relocations {
+            if let Location::Rodata(offset) = location
+                && relocation.0 == RelocationType::RSbf64Relative as u32
+                && let Some(target) = self.data_address(*offset) {
+                functions.push(self.containing(target).0);
+            }
+        }
+        for location in functions {
+            self.label(location, "function");
+        }
+        for location in jumps {
+            self.label(location, "jump");
+        }
+    }
+
+    // Internal calls, the ones without a relocation
+    fn call_target(&self, instruction: &Instruction) -> Option<u64> {
+        if instruction.src != 1 || self.relocations.contains_key(&Location::Text(instruction.offset)) {
+            return None;
+        }
+        instruction.target(instruction.imm).filter(|target| self.boundaries.contains(target))
+    }
+
+    fn jump_target(&self, instruction: &Instruction) -> Option<u64> {
+        instruction.target(instruction.off as i64).filter(|target| self.boundaries.contains(target))
+    }
+
+    // The address an lddw loads, if it's one. Legacy programs relocate them,
+    // v3 maps .rodata at a fixed address instead
+    fn address(&self, instruction: &Instruction) -> Option<Location> {
+        let address = instruction.imm as u64;
+        match self.relocations.get(&Location::Text(instruction.offset)) {
+            Some((rel_type, _)) if *rel_type == RelocationType::RSbf64Relative as u32 => self.locate(address, true),
+            _ if self.version.enable_stricter_elf_headers() && address >= ProgramHeader::MM_RODATA_START =>
+                self.locate(address, true).filter(|location| matches!(location, Location::Rodata(_))),
+            _ => None,
+        }
+    }
+
+    // The address held by a relocated 8 byte value in .rodata, v0 keeps it in
+    // the upper half
+    fn data_address(&self, offset: u64) -> Option<Location> {
+        let value = u64::from_le_bytes(self.rodata_bytes.get(offset as usize..offset as usize + 8)?.try_into().unwrap());
+        let address = match self.version {
+            SbpfVersion::V0 if value as u32 != 0 => return None,
+            SbpfVersion::V0 => value >> 32,
+            _ => value,
+        };
+        self.locate(address, true)
+    }
+
+    // Labels in .text go on instructions, anything else is relative to the
+    // instruction it points into
+    fn containing(&self, location: Location) -> (Location, u64) {
+        match location {
+            Location::Text(offset) => {
+                let start = self.boundaries.range(..=offset).next_back().copied().unwrap_or_default();
+                (Location::Text(start), offset - start)
+            }
+            location => (location, 0),
+        }
+    }
+
+    fn reference(&self, location: Location) -> String {
+        let (location, delta) = self.containing(location);
+        let label = &self.labels[&location];
+        if delta == 0 { label.clone() } else { format!("{} + {}", label, delta) }
+    }
+
+    fn text_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for instruction in &self.instructions {
+            if let Some(label) = self.labels.get(&Location::Text(instruction.offset)) {
+                lines.push(format!("{}:", label));
+            }
+            lines.push(self.instruction(instruction));
+        }
+        lines
+    }
+
+    fn instruction(&self, instruction: &Instruction) -> String {
+        let Instruction { dst, src, off, imm, .. } = *instruction;
+        let Some(opcode) = instruction.opcode else {
+            return format!(".8byte 0x{:016x} # unknown opcode 0x{:02x}", self.raw(instruction), instruction.code);
+        };
+        let mnemonic = opcode.to_str();
+        // the src bit of the alu and jump classes
+        let register_form = instruction.code & 0x08 != 0;
+        let memory = |register: u8| match off {
+            0.. => format!("[r{} + {}]", register, off),
+            _ => format!("[r{} - {}]", register, -(off as i32)),
+        };
+        match opcode {
+            Opcode::Lddw => match self.address(instruction) {
+                Some(location) => format!("lddw r{}, {}", dst, self.reference(location)),
+                None if (i32::MIN as i64..=i32::MAX as i64).contains(&imm) => format!("lddw r{}, {}", dst, imm),
+                None => format!("lddw r{}, 0x{:x}", dst, imm as u64),
+            },
+            Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw => format!("{} r{}, {}", mnemonic, dst, memory(src)),
+            // stores of an immediate are written with a register, which
+            // only sets the unused src field
+            Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw if imm != 0 =>
+                format!("{} {}, {} {}", mnemonic, memory(dst), imm, UNSUPPORTED),
+            Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw
+            | Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => format!("{} {}, r{}", mnemonic, memory(dst), src),
+            Opcode::Neg32 | Opcode::Neg64 => format!("{} r{}", mnemonic, dst),
+            // the assembler writes `sub dst, imm` as an add on v2
+            Opcode::Sub32Imm | Opcode::Sub64Imm if self.version.swap_sub_reg_imm_operands() =>
+                format!(".8byte 0x{:016x} # r{} = {} - r{}, not supported by the assembler", self.raw(instruction), dst, imm, dst),
+            Opcode::Le | Opcode::Be => format!("{} r{}, {}", mnemonic, dst, imm),
+            Opcode::Exit => mnemonic.to_string(),
+            Opcode::Callx if self.version.callx_uses_src_reg() => format!("callx r{}", src),
+            Opcode::Callx => format!("callx r{}", imm),
+            Opcode::Call => self.call(instruction),
+            Opcode::Ja => match self.jump_target(instruction) {
+                Some(target) => format!("ja {}", self.labels[&Location::Text(target)]),
+                None => format!("ja {}", off),
+            },
+            opcode if opcode.is_jump() => {
+                let target = match self.jump_target(instruction) {
+                    Some(target) => self.labels[&Location::Text(target)].clone(),
+                    None => format!("{} {}", off, UNSUPPORTED),
+                };
+                match register_form {
+                    true => format!("{} r{}, r{}, {}", mnemonic, dst, src, target),
+                    false => format!("{} r{}, {}, {}", mnemonic, dst, imm, target),
+                }
+            }
+            _ if register_form && opcode != Opcode::Hor64Imm => format!("{} r{}, r{}", mnemonic, dst, src),
+            _ => format!("{} r{}, {}", mnemonic, dst, imm),
+        }
+    }
+
+    // Syscalls are named through their relocation, or their hash once
+    // they're static
+    fn call(&self, instruction: &Instruction) -> String {
+        if let Some((rel_type, name)) = self.relocations.get(&Location::Text(instruction.offset))
+            && *rel_type == RelocationType::RSbfSyscall as u32 {
+            return format!("call {}", name);
+        }
+        if let Some(target) = self.call_target(instruction) {
+            return format!("call {}", self.labels[&Location::Text(target)]);
+        }
+        let hash = instruction.imm as u32;
+        match SYSCALLS.iter().find(|syscall| murmur3_32(syscall.as_bytes()) == hash) {
+            Some(syscall) if instruction.src == 0 => format!("call {}", syscall),
+            _ => format!("call 0x{:08x} {}", hash, UNSUPPORTED),
+        }
+    }
+
+    fn raw(&self, instruction: &Instruction) -> u64 {
+        let mut bytes = [instruction.code, instruction.src << 4 | instruction.dst, 0, 0, 0, 0, 0, 0];
+        bytes[2..4].copy_from_slice(&instruction.off.to_le_bytes());
+        bytes[4..8].copy_from_slice(&(instruction.imm as i32).to_le_bytes());
+        u64::from_le_bytes(bytes)
+    }
+
+    // Data is split at every label and relocated value, each piece is
+    // dumped as text, zeros or plain bytes
+    fn rodata_lines(&self) -> Vec<String> {
+        let bytes = self.rodata_bytes;
+        let mut splits: BTreeSet<u64> = self.labels.keys()
+            .filter_map(|location| match location {
+                Location::Rodata(offset) => Some(*offset),
+                _ => None,
+            })
+            .collect();
+        let relocated: BTreeMap<u64, Location> = self.relocations.iter()
+            .filter_map(|(location, (rel_type, _))| match location {
+                Location::Rodata(offset) if *rel_type == RelocationType::RSbf64Relative as u32
+                    && *offset + 8 <= bytes.len() as u64 => self.data_address(*offset).map(|target| (*offset, target)),
+                _ => None,
+            })
+            .collect();
+        splits.extend(relocated.keys().flat_map(|offset| [*offset, offset + 8]));
+        splits.insert(0);
+        splits.insert(bytes.len() as u64);
+
+        let mut lines = Vec::new();
+        let splits: Vec<_> = splits.into_iter().collect();
+        for window in splits.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            let mut statements = match relocated.get(&start) {
+                Some(target) => vec![format!(".quad {}", self.reference(*target))],
+                None => data(&bytes[start as usize..end as usize]),
+            };
+            if let Some(label) = self.labels.get(&Location::Rodata(start)) {
+                statements[0] = format!("{}: {}", label, statements[0]);
+            }
+            lines.extend(statements);
+        }
+        if let Some(label) = self.labels.get(&Location::Rodata(bytes.len() as u64)) {
+            lines.push(format!("{}:", label));
+        }
+        lines
+    }
+}
+
+// Printable runs become strings, a NUL right after one ends it with .asciz,
+// runs of zeros are filled with .zero and the rest is listed as bytes
+fn data(bytes: &[u8]) -> Vec<String> {
+    let is_text = |byte: &u8| byte.is_ascii_graphic() || matches!(byte, b' ' | b'\n' | b'\t' | b'\r');
+    let mut lines = Vec::new();
+    let mut pending = Vec::new();
+    let flush = |pending: &mut Vec<u8>, lines: &mut Vec<String>| {
+        for row in pending.chunks(16) {
+            let row: Vec<_> = row.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            lines.push(format!(".byte {}", row.join(", ")));
+        }
+        pending.clear();
+    };
+    let mut rest = bytes;
+    while let Some(byte) = rest.first() {
+        let text = rest.iter().take_while(|byte| is_text(byte)).count();
+        let zeros = rest.iter().take_while(|byte| **byte == 0).count();
+        // short strings still count when they fill the whole piece
+        let whole = text > 0 && (text == rest.len() || text + 1 == rest.len() && rest[text] == 0);
+        if text >= 4 || whole {
+            flush(&mut pending, &mut lines);
+            let directive = if rest.get(text) == Some(&0) { ".asciz" } else { ".ascii" };
+            lines.push(format!("{} \"{}\"", directive, escape(&rest[..text])));
+            rest = &rest[text + (directive == ".asciz") as usize..];
+        } else if zeros >= 4 {
+            flush(&mut pending, &mut lines);
+            lines.push(format!(".zero {}", zeros));
+            rest = &rest[zeros..];
+        } else {
+            pending.push(*byte);
+            rest = &rest[1..];
+        }
+    }
+    flush(&mut pending, &mut lines);
+    lines
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for byte in bytes {
+        match byte {
+            b'\n' => text.push_str("\\n"),
+            b'\t' => text.push_str("\\t"),
+            b'\r' => text.push_str("\\r"),
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            byte => text.push(*byte as char),
+        }
+    }
+    text
+}
@@ -0,0 +1,171 @@
+use anyhow::{Error, Result};
+
+use crate::header::SectionHeader;
+
+// Reads back the parts of an ELF file the disassembler needs, the sections,
+// symbol tables and dynamic relocations. Enough of the format for the files
+// `Program` writes and the ones built by cargo-build-sbf
+
+#[derive(Debug, Clone)]
+pub struct ElfSection {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub addralign: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ElfSymbol {
+    pub name: String,
+    // binding in the high nibble, type in the low one
+    pub info: u8,
+    pub shndx: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+impl ElfSymbol {
+    pub fn is_function(&self) -> bool {
+        self.info & 0xf == 2
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.info >> 4 == 1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ElfRelocation {
+    pub offset: u64,
+    pub rel_type: u32,
+    // index into .dynsym
+    pub symbol: u32,
+}
+
+pub struct ElfFile<'a> {
+    bytes: &'a [u8],
+    pub e_entry: u64,
+    pub e_flags: u32,
+    pub sections: Vec<ElfSection>,
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 64 || bytes[..4] != [0x7f, b'E', b'L', b'F'] {
+            return Err(Error::msg("Not an ELF file"));
+        }
+        if bytes[4] != 2 || bytes[5] != 1 {
+            return Err(Error::msg("Only 64 bit little endian ELF files are supported"));
+        }
+        let e_entry = read_u64(bytes, 24)?;
+        let e_shoff = read_u64(bytes, 40)? as usize;
+        let e_flags = read_u32(bytes, 48)?;
+        let e_shentsize = read_u16(bytes, 58)? as usize;
+        let e_shnum = read_u16(bytes, 60)? as usize;
+        let e_shstrndx = read_u16(bytes, 62)? as usize;
+
+        let mut headers = Vec::new();
+        for idx in 0..e_shnum {
+            let header = e_shoff.checked_add(idx * e_shentsize).ok_or_else(|| Error::msg("Unexpected end of file"))?;
+            headers.push((
+                read_u32(bytes, header)?,
+                ElfSection {
+                    name: String::new(),
+                    sh_type: read_u32(bytes, header + 4)?,
+                    flags: read_u64(bytes, header + 8)?,
+                    addr: read_u64(bytes, header + 16)?,
+                    offset: read_u64(bytes, header + 24)?,
+                    size: read_u64(bytes, header + 32)?,
+                    link: read_u32(bytes, header + 40)?,
+                    addralign: read_u64(bytes, header + 48)?,
+                },
+            ));
+        }
+        let mut elf = Self { bytes, e_entry, e_flags, sections: Vec::new() };
+        let names = match headers.get(e_shstrndx) {
+            Some((_, shstrtab)) => elf.data(shstrtab)?,
+            None => &[],
+        };
+        for (name, mut section) in headers {
+            section.name = read_str(names, name as usize);
+            elf.sections.push(section);
+        }
+        Ok(elf)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&ElfSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    pub fn data(&self, section: &ElfSection) -> Result<&'a [u8]> {
+        if section.sh_type == SectionHeader::SHT_NOBITS {
+            return Ok(&[]);
+        }
+        let start = section.offset as usize;
+        start.checked_add(section.size as usize)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or_else(|| Error::msg(format!("Section {} lies outside of the file", section.name)))
+    }
+
+    // The entries of `.dynsym` or `.symtab`, named through their linked
+    // string table
+    pub fn symbols(&self, name: &str) -> Result<Vec<ElfSymbol>> {
+        let Some(table) = self.section(name) else { return Ok(Vec::new()) };
+        let data = self.data(table)?;
+        let names = match self.sections.get(table.link as usize) {
+            Some(strtab) => self.data(strtab)?,
+            None => &[],
+        };
+        let mut symbols = Vec::new();
+        for entry in data.chunks_exact(24) {
+            symbols.push(ElfSymbol {
+                name: read_str(names, read_u32(entry, 0)? as usize),
+                info: entry[4],
+                shndx: read_u16(entry, 6)?,
+                value: read_u64(entry, 8)?,
+                size: read_u64(entry, 16)?,
+            });
+        }
+        Ok(symbols)
+    }
+
+    pub fn relocations(&self) -> Result<Vec<ElfRelocation>> {
+        let Some(rel_dyn) = self.section(".rel.dyn") else { return Ok(Vec::new()) };
+        let mut relocations = Vec::new();
+        for entry in self.data(rel_dyn)?.chunks_exact(16) {
+            let info = read_u64(entry, 8)?;
+            relocations.push(ElfRelocation { offset: read_u64(entry, 0)?, rel_type: info as u32, symbol: (info >> 32) as u32 });
+        }
+        Ok(relocations)
+    }
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N]> {
+    offset.checked_add(N)
+        .and_then(|end| bytes.get(offset..end))
+        .map(|slice| slice.try_into().unwrap())
+        .ok_or_else(|| Error::msg("Unexpected end of file"))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    read_bytes(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    read_bytes(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    read_bytes(bytes, offset).map(u64::from_le_bytes)
+}
+
+// NUL terminated string at `offset`, empty if out of bounds
+fn read_str(bytes: &[u8], offset: usize) -> String {
+    let bytes = bytes.get(offset..).unwrap_or_default();
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
@@ -3,75 +3,238 @@ use std::ops::Range;
 
 // labels could be overridden by passing a valid custom_label in the error variant
 // if not provided, the label will use default messages from below
+// codes are stable, new errors take the next free code and retired ones are
+// never reused
 define_compile_errors! {
     // Lexical errors
     InvalidNumber {
+        code = "E0001",
         error = "Invalid number '{number}'",
         label = "Invalid number",
         fields = { number: String, span: Range<usize> }
     },
     InvalidRegister {
+        code = "E0002",
         error = "Invalid register '{register}'",
         label = "Invalid register",
         fields = { register: String, span: Range<usize> }
     },
     UnexpectedCharacter {
+        code = "E0003",
         error = "Unexpected character '{character}'",
         label = "Unexpected character",
         fields = { character: char, span: Range<usize> }
     },
     UnterminatedStringLiteral {
+        code = "E0004",
         error = "Unterminated string literal",
         label = "Unterminated string literal",
         fields = { span: Range<usize> }
     },
+    InvalidEscapeSequence {
+        code = "E0005",
+        error = "Invalid escape sequence '{escape}'",
+        label = "Expected one of \\n \\t \\r \\0 \\\\ \\\" \\' \\xNN",
+        fields = { escape: String, span: Range<usize> }
+    },
+    InvalidCharLiteral {
+        code = "E0006",
+        error = "Invalid character literal",
+        label = "Expected a single character, e.g. 'A' or '\\n'",
+        fields = { span: Range<usize> }
+    },
     // Syntactic errors
     InvalidGlobalDecl {
+        code = "E0007",
         error = "Invalid global declaration",
         label = "Expected <identifier> for entry label",
         fields = { span: Range<usize> }
     },
     InvalidExternDecl {
+        code = "E0008",
         error = "Invalid extern declaration",
         label = "Invalid extern declaration",
         fields = { span: Range<usize> }
     },
     InvalidRodataDecl {
+        code = "E0009",
         error = "Invalid rodata declaration",
         label = "Invalid rodata declaration",
         fields = { span: Range<usize> }
     },
+    InvalidTypeDecl {
+        code = "E0010",
+        error = "Invalid type declaration",
+        label = "Invalid type declaration",
+        fields = { span: Range<usize> }
+    },
+    InvalidSizeDecl {
+        code = "E0011",
+        error = "Invalid size declaration",
+        label = "Invalid size declaration",
+        fields = { span: Range<usize> }
+    },
+    InvalidAlignDecl {
+        code = "E0012",
+        error = "Invalid alignment directive",
+        label = "Invalid alignment directive",
+        fields = { span: Range<usize> }
+    },
     InvalidEquDecl {
+        code = "E0013",
         error = "Invalid equ declaration",
         label = "Invalid equ declaration",
         fields = { span: Range<usize> }
     },
     InvalidDirective {
+        code = "E0014",
         error = "Invalid directive '{directive}'",
         label = "Invalid directive",
         fields = { directive: String, span: Range<usize> }
     },
     InvalidInstruction {
+        code = "E0015",
         error = "Invalid '{instruction}' instruction",
         label = "Invalid instruction",
         fields = { instruction: String, span: Range<usize> }
     },
+    UnknownInstruction {
+        code = "E0016",
+        error = "Unknown instruction '{instruction}'",
+        label = "Unknown instruction",
+        fields = { instruction: String, span: Range<usize> }
+    },
     UnexpectedToken {
+        code = "E0017",
         error = "Unexpected token '{token}'",
         label = "Unexpected token",
         fields = { token: String, span: Range<usize> }
     },
+    ExpectedEndOfLine {
+        code = "E0018",
+        error = "Expected end of line",
+        label = "Unexpected tokens after the statement",
+        fields = { span: Range<usize> }
+    },
+    InvalidExpression {
+        code = "E0019",
+        error = "Invalid expression",
+        label = "Expected a number, a constant or a parenthesized expression",
+        fields = { span: Range<usize> }
+    },
 
     // Semantic errors
     UndefinedLabel {
+        code = "E0020",
         error = "Undefined label '{label}'",
         label = "Undefined label",
-        fields = { label: String, span: Range<usize> }
+        fields = { label: String, span: Range<usize>, suggestion: Option<String> }
+    },
+    DuplicateLabel {
+        code = "E0021",
+        error = "Label '{label}' is defined more than once",
+        label = "Redefined here",
+        fields = { label: String, span: Range<usize>, original: Range<usize> }
+    },
+    DuplicateConstant {
+        code = "E0022",
+        error = "Constant '{name}' is defined more than once",
+        label = "Redefined here",
+        fields = { name: String, span: Range<usize>, original: Range<usize> }
+    },
+    ImmediateOutOfRange {
+        code = "E0023",
+        error = "Value {value} is out of range",
+        label = "Does not fit in the instruction",
+        fields = { value: i128, span: Range<usize> }
+    },
+    BranchOutOfRange {
+        code = "E0024",
+        error = "Branch offset {offset} is out of range",
+        label = "Jump offsets are limited to 16 bits, -32768 to 32767 instructions",
+        fields = { offset: i64, span: Range<usize> }
+    },
+    ReadOnlyRegister {
+        code = "E0025",
+        error = "Register r10 is read-only",
+        label = "r10 is the frame pointer and can't be written",
+        fields = { span: Range<usize> }
+    },
+    UnsupportedOpcode {
+        code = "E0026",
+        error = "'{opcode}' is not supported on sbpf {version}",
+        label = "Unsupported on the selected target",
+        fields = { opcode: String, version: String, span: Range<usize> }
+    },
+    DivisionByZero {
+        code = "E0027",
+        error = "Division by zero in constant expression",
+        label = "Evaluates to zero",
+        fields = { span: Range<usize> }
+    },
+    ExpressionOverflow {
+        code = "E0028",
+        error = "Constant expression overflows 64 bits",
+        label = "Overflows 64 bits",
+        fields = { span: Range<usize> }
+    },
+    InvalidAddressExpression {
+        code = "E0029",
+        error = "Invalid use of a label address",
+        label = "Only label differences and offsets from a label are allowed",
+        fields = { span: Range<usize> }
     },
 }
 
 
 use codespan_reporting::diagnostic::{Diagnostic, Label};
+use crate::messages::FIRST_DEFINED_HERE;
+use crate::opcode::Opcode;
+use crate::parser::DIRECTIVES;
+use crate::suggest::suggest;
+
+impl CompileError {
+    // Whether the source can't be read as statements at all, as opposed to
+    // statements that don't assemble
+    pub fn is_syntax_error(&self) -> bool {
+        !matches!(self,
+            Self::UndefinedLabel { .. } | Self::DuplicateLabel { .. } | Self::DuplicateConstant { .. }
+            | Self::ImmediateOutOfRange { .. } | Self::BranchOutOfRange { .. } | Self::ReadOnlyRegister { .. }
+            | Self::UnsupportedOpcode { .. } | Self::DivisionByZero { .. } | Self::ExpressionOverflow { .. }
+            | Self::InvalidAddressExpression { .. })
+    }
+
+    // The earlier definition a redefinition clashes with
+    pub fn original(&self) -> Option<&Range<usize>> {
+        match self {
+            Self::DuplicateLabel { original, .. } | Self::DuplicateConstant { original, .. } => Some(original),
+            _ => None,
+        }
+    }
+
+    // The closest known name to a misspelled one
+    pub fn suggestion(&self) -> Option<String> {
+        match self {
+            Self::UndefinedLabel { suggestion, .. } => suggestion.clone(),
+            Self::UnknownInstruction { instruction, .. } => suggest(instruction, Opcode::mnemonics()).map(str::to_string),
+            Self::InvalidDirective { directive, .. } => suggest(directive, DIRECTIVES.iter().copied()).map(|name| format!(".{}", name)),
+            _ => None,
+        }
+    }
+
+    // The operand forms of the instruction that failed to parse, or of the
+    // one suggested for an unknown mnemonic
+    fn expected_forms(&self) -> Option<String> {
+        let mnemonic = match self {
+            Self::InvalidInstruction { instruction, .. } => instruction.clone(),
+            Self::UnknownInstruction { .. } => self.suggestion()?,
+            _ => return None,
+        };
+        let forms = Opcode::from_str(&mnemonic).ok()?.operand_forms();
+        let forms: Vec<_> = forms.iter().map(|form| format!("`{}`", format!("{} {}", mnemonic, form).trim_end())).collect();
+        (!forms.is_empty()).then(|| format!("expected {}", forms.join(" or ")))
+    }
+}
 
 pub trait AsDiagnostic {
     // currently only support single source file reporting
@@ -80,9 +243,20 @@ pub trait AsDiagnostic {
 
 impl AsDiagnostic for CompileError {
     fn to_diagnostic(&self) -> Diagnostic<()> {
+        let mut labels = vec![Label::primary((), self.span().start..self.span().end).with_message(self.label())];
+        if let Some(original) = self.original() {
+            labels.push(Label::secondary((), original.clone()).with_message(FIRST_DEFINED_HERE));
+        }
+        let mut notes = Vec::new();
+        if let Some(suggestion) = self.suggestion() {
+            notes.push(format!("help: did you mean `{}`?", suggestion));
+        }
+        notes.extend(self.expected_forms());
         Diagnostic::error()
+            .with_code(self.code())
             .with_message(self.to_string())
-            .with_labels(vec![Label::primary((), self.span().start..self.span().end).with_message(self.label())])
+            .with_labels(labels)
+            .with_notes(notes)
     }
 }
 
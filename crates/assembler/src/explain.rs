@@ -0,0 +1,48 @@
+// Long-form explanations of error and warning codes for `sbpf explain`. Each
+// one shows an erroneous example followed by a fixed one, the examples are
+// checked by the tests
+const EXPLANATIONS: &[(&str, &str)] = &[
+    ("E0001", include_str!("../explanations/E0001.md")),
+    ("E0002", include_str!("../explanations/E0002.md")),
+    ("E0003", include_str!("../explanations/E0003.md")),
+    ("E0004", include_str!("../explanations/E0004.md")),
+    ("E0005", include_str!("../explanations/E0005.md")),
+    ("E0006", include_str!("../explanations/E0006.md")),
+    ("E0007", include_str!("../explanations/E0007.md")),
+    ("E0008", include_str!("../explanations/E0008.md")),
+    ("E0009", include_str!("../explanations/E0009.md")),
+    ("E0010", include_str!("../explanations/E0010.md")),
+    ("E0011", include_str!("../explanations/E0011.md")),
+    ("E0012", include_str!("../explanations/E0012.md")),
+    ("E0013", include_str!("../explanations/E0013.md")),
+    ("E0014", include_str!("../explanations/E0014.md")),
+    ("E0015", include_str!("../explanations/E0015.md")),
+    ("E0016", include_str!("../explanations/E0016.md")),
+    ("E0017", include_str!("../explanations/E0017.md")),
+    ("E0018", include_str!("../explanations/E0018.md")),
+    ("E0019", include_str!("../explanations/E0019.md")),
+    ("E0020", include_str!("../explanations/E0020.md")),
+    ("E0021", include_str!("../explanations/E0021.md")),
+    ("E0022", include_str!("../explanations/E0022.md")),
+    ("E0023", include_str!("../explanations/E0023.md")),
+    ("E0024", include_str!("../explanations/E0024.md")),
+    ("E0025", include_str!("../explanations/E0025.md")),
+    ("E0026", include_str!("../explanations/E0026.md")),
+    ("E0027", include_str!("../explanations/E0027.md")),
+    ("E0028", include_str!("../explanations/E0028.md")),
+    ("E0029", include_str!("../explanations/E0029.md")),
+    ("W0001", include_str!("../explanations/W0001.md")),
+    ("W0002", include_str!("../explanations/W0002.md")),
+    ("W0003", include_str!("../explanations/W0003.md")),
+    ("W0004", include_str!("../explanations/W0004.md")),
+    ("W0005", include_str!("../explanations/W0005.md")),
+];
+
+// Codes are matched case-insensitively, `e7` is E0007
+pub fn explain(code: &str) -> Option<&'static str> {
+    let code = code.to_uppercase();
+    let (kind, number) = code.split_at_checked(1)?;
+    let number: u16 = number.parse().ok()?;
+    let code = format!("{}{:04}", kind, number);
+    EXPLANATIONS.iter().find(|(c, _)| *c == code).map(|(_, text)| *text)
+}
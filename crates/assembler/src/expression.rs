@@ -0,0 +1,203 @@
+use crate::errors::CompileError;
+use crate::lexer::{ImmediateValue, Op, Token};
+use crate::messages::*;
+use std::ops::Range;
+
+// Constant expressions in operands and directives, e.g. `HEADER + 8 * 32`.
+// Labels and `.`, the location counter, stand for their addresses
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Value(ImmediateValue, Range<usize>),
+    Symbol(String, Range<usize>),
+    Neg(Box<Expr>, Range<usize>),
+    Not(Box<Expr>, Range<usize>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Expr::Value(_, span) | Expr::Symbol(_, span) => span.clone(),
+            Expr::Neg(expr, span) | Expr::Not(expr, span) => span.start..expr.span().end,
+            Expr::Binary(_, lhs, rhs) => lhs.span().start..rhs.span().end,
+        }
+    }
+
+    // Every symbol referenced, in source order
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Value(_, _) => Vec::new(),
+            Expr::Symbol(name, _) => vec![name.as_str()],
+            Expr::Neg(expr, _) | Expr::Not(expr, _) => expr.symbols(),
+            Expr::Binary(_, lhs, rhs) => [lhs.symbols(), rhs.symbols()].concat(),
+        }
+    }
+
+    // Symbols are looked up through `resolve`, `.` included, an unknown one
+    // is reported as an undefined label
+    pub fn evaluate(&self, resolve: &dyn Fn(&str) -> Option<Resolved>) -> Result<Resolved, CompileError> {
+        // a lone literal keeps its kind, e.g. hex literals stay addresses
+        if let Expr::Value(value, _) = self {
+            return Ok(Resolved::Constant(value.clone()));
+        }
+        let term = self.evaluate_term(resolve)?;
+        // label addresses cancel out in differences, a single one left over
+        // makes the result an address too
+        match term.labels {
+            0 => Ok(Resolved::Constant(ImmediateValue::from_i128(term.value, term.is_addr))),
+            1 => Ok(Resolved::Address(term.value as u64)),
+            _ => Err(CompileError::InvalidAddressExpression { span: self.span(), custom_label: None }),
+        }
+    }
+
+    // Values are kept in an i128 so both i64 and u64 operands fit, every
+    // intermediate result has to fit in one of them
+    fn evaluate_term(&self, resolve: &dyn Fn(&str) -> Option<Resolved>) -> Result<Term, CompileError> {
+        let overflow = || CompileError::ExpressionOverflow { span: self.span(), custom_label: None };
+        let term = match self {
+            Expr::Value(value, _) => Term { value: value.to_i128(), labels: 0, is_addr: value.is_addr() },
+            Expr::Symbol(name, span) => match resolve(name) {
+                Some(Resolved::Constant(value)) => Term { value: value.to_i128(), labels: 0, is_addr: value.is_addr() },
+                Some(Resolved::Address(address)) => Term { value: address as i128, labels: 1, is_addr: false },
+                None => return Err(CompileError::UndefinedLabel { label: name.clone(), span: span.clone(), suggestion: None, custom_label: None }),
+            },
+            Expr::Neg(expr, _) => {
+                let term = expr.evaluate_term(resolve)?;
+                Term { value: -term.value, labels: -term.labels, is_addr: false }
+            }
+            Expr::Not(expr, _) => {
+                let value = expr.evaluate_term(resolve)?.constant(self)?;
+                // complement within 64 bits, signed unless the operand only fits unsigned
+                let value = if value > i64::MAX as i128 { !(value as u64) as i128 } else { !value };
+                Term { value, labels: 0, is_addr: false }
+            }
+            Expr::Binary(op @ (Op::Add | Op::Sub), lhs, rhs) => {
+                let (a, b) = (lhs.evaluate_term(resolve)?, rhs.evaluate_term(resolve)?);
+                let (value, labels) = match op {
+                    Op::Add => (a.value.checked_add(b.value), a.labels + b.labels),
+                    _ => (a.value.checked_sub(b.value), a.labels - b.labels),
+                };
+                // offsetting an address keeps it an address
+                Term { value: value.ok_or_else(overflow)?, labels, is_addr: a.is_addr || b.is_addr }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.evaluate_term(resolve)?.constant(self)?;
+                let b = rhs.evaluate_term(resolve)?.constant(self)?;
+                let divisor_is_zero = || CompileError::DivisionByZero { span: rhs.span(), custom_label: None };
+                let shift_amount = || u32::try_from(b).ok().filter(|b| *b < 64)
+                    .ok_or(CompileError::ExpressionOverflow { span: rhs.span(), custom_label: Some(EXPECTS_SHIFT_AMOUNT.to_string()) });
+                let value = match op {
+                    Op::Mul => a.checked_mul(b).ok_or_else(overflow)?,
+                    Op::Div if b == 0 => return Err(divisor_is_zero()),
+                    Op::Div => a / b,
+                    Op::Mod if b == 0 => return Err(divisor_is_zero()),
+                    Op::Mod => a % b,
+                    // can't overflow the i128, the result is range checked below
+                    Op::Shl => a << shift_amount()?,
+                    Op::Shr => a >> shift_amount()?,
+                    Op::And => a & b,
+                    Op::Or => a | b,
+                    Op::Xor => a ^ b,
+                    Op::Add | Op::Sub | Op::Not => unreachable!("handled above or unary"),
+                };
+                Term { value, labels: 0, is_addr: false }
+            }
+        };
+        if term.value < i64::MIN as i128 || term.value > u64::MAX as i128 {
+            return Err(overflow());
+        }
+        Ok(term)
+    }
+}
+
+// What a symbol stands for, constants are plain numbers while label
+// addresses move with the program
+#[derive(Debug, Clone)]
+pub enum Resolved {
+    Constant(ImmediateValue),
+    Address(u64),
+}
+
+// An intermediate result, `labels` counts the label addresses added minus
+// the ones subtracted
+struct Term {
+    value: i128,
+    labels: i32,
+    is_addr: bool,
+}
+
+impl Term {
+    // only plain numbers can be scaled, masked or shifted
+    fn constant(&self, expr: &Expr) -> Result<i128, CompileError> {
+        match self.labels {
+            0 => Ok(self.value),
+            _ => Err(CompileError::InvalidAddressExpression { span: expr.span(), custom_label: None }),
+        }
+    }
+}
+
+// C precedence, all binary operators are left associative
+fn precedence(op: &Op) -> Option<u8> {
+    match op {
+        Op::Or => Some(1),
+        Op::Xor => Some(2),
+        Op::And => Some(3),
+        Op::Shl | Op::Shr => Some(4),
+        Op::Add | Op::Sub => Some(5),
+        Op::Mul | Op::Div | Op::Mod => Some(6),
+        Op::Not => None,
+    }
+}
+
+// Whether the token can start an expression
+pub fn starts_expression(token: &Token) -> bool {
+    matches!(token,
+        Token::ImmediateValue(_, _) | Token::Identifier(_, _) | Token::LeftParen(_)
+        | Token::BinaryOp(Op::Add | Op::Sub | Op::Not, _))
+}
+
+// Parses the expression starting at `idx`, returns it with the index of the
+// first token past it
+pub fn parse_expression(tokens: &[Token], idx: usize) -> Result<(Expr, usize), CompileError> {
+    parse_binary(tokens, idx, 1)
+}
+
+fn parse_binary(tokens: &[Token], idx: usize, min_precedence: u8) -> Result<(Expr, usize), CompileError> {
+    let (mut lhs, mut idx) = parse_unary(tokens, idx)?;
+    while let Some(Token::BinaryOp(op, _)) = tokens.get(idx)
+        && let Some(precedence) = precedence(op)
+        && precedence >= min_precedence {
+        let (rhs, next) = parse_binary(tokens, idx + 1, precedence + 1)?;
+        lhs = Expr::Binary(op.clone(), Box::new(lhs), Box::new(rhs));
+        idx = next;
+    }
+    Ok((lhs, idx))
+}
+
+fn parse_unary(tokens: &[Token], idx: usize) -> Result<(Expr, usize), CompileError> {
+    match tokens.get(idx) {
+        Some(Token::ImmediateValue(value, span)) => Ok((Expr::Value(value.clone(), span.clone()), idx + 1)),
+        Some(Token::Identifier(name, span)) => Ok((Expr::Symbol(name.clone(), span.clone()), idx + 1)),
+        Some(Token::BinaryOp(Op::Add, _)) => parse_unary(tokens, idx + 1),
+        Some(Token::BinaryOp(Op::Sub, span)) => {
+            let (expr, next) = parse_unary(tokens, idx + 1)?;
+            Ok((Expr::Neg(Box::new(expr), span.clone()), next))
+        }
+        Some(Token::BinaryOp(Op::Not, span)) => {
+            let (expr, next) = parse_unary(tokens, idx + 1)?;
+            Ok((Expr::Not(Box::new(expr), span.clone()), next))
+        }
+        Some(Token::LeftParen(span)) => {
+            let (expr, next) = parse_binary(tokens, idx + 1, 1)?;
+            match tokens.get(next) {
+                Some(Token::RightParen(_)) => Ok((expr, next + 1)),
+                _ => Err(CompileError::InvalidExpression { span: span.clone(), custom_label: Some(EXPECTS_CLOSING_PAREN.to_string()) }),
+            }
+        }
+        Some(token) => Err(CompileError::InvalidExpression { span: token.span().clone(), custom_label: None }),
+        None => {
+            let end = tokens.last().map_or(0, |token| token.span().end);
+            Err(CompileError::InvalidExpression { span: end..end, custom_label: None })
+        }
+    }
+}
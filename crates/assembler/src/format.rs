@@ -0,0 +1,182 @@
+use crate::errors::CompileError;
+use crate::lexer::{Op, Token, tokenize};
+use crate::parse_source;
+use crate::version::SbpfVersion;
+use codespan_reporting::files::SimpleFile;
+
+// Canonical layout for `sbpf fmt`. Labels go in column 0, statements are
+// indented, and symbol and section directives stay in column 0. A label that
+// shares its line with data keeps it, the data of a block lines up behind
+// the longest such label. Operands are re-spaced, literals and comments are
+// kept as written
+
+const INDENT: usize = 2;
+
+// Directives that start a section or declare symbols
+const TOP_LEVEL_DIRECTIVES: &[&str] = &["globl", "global", "entry", "extern", "rodata", "section", "equ", "type", "size"];
+
+enum Line {
+    Blank,
+    Comment(String),
+    Code {
+        label: Option<String>,
+        statement: String,
+        top_level: bool,
+        // after .rodata
+        data: bool,
+        comment: Option<String>,
+    },
+}
+
+// Only syntax errors stop formatting, the rest are for `sbpf build`
+pub fn format(source: &str) -> Result<String, Vec<CompileError>> {
+    let (tokens, mut errors) = tokenize(source);
+    let file = SimpleFile::new(String::new(), source.to_string());
+    if let Err(parse_errors) = parse_source(&file, SbpfVersion::default()) {
+        errors.extend(parse_errors.into_iter().filter(|error| error.is_syntax_error()));
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|error| error.span().start);
+        errors.dedup_by_key(|error| error.span().clone());
+        return Err(errors);
+    }
+    Ok(render(&lines(source, &tokens)))
+}
+
+fn lines(source: &str, tokens: &[Token]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut rodata_phase = false;
+    let mut previous_end = 0;
+    for line in tokens.split_inclusive(|token| matches!(token, Token::Newline(_))) {
+        let Some(first) = line.first() else { continue };
+        // empty lines don't produce any tokens
+        if source[previous_end..first.span().start].contains('\n') {
+            lines.push(Line::Blank);
+        }
+        previous_end = line.last().unwrap().span().end;
+
+        let mut tokens: Vec<_> = line.iter().filter(|token| !matches!(token, Token::Newline(_))).collect();
+        let comment = match tokens.last() {
+            Some(Token::Comment(text, _)) => {
+                let text = text.clone();
+                tokens.pop();
+                Some(text)
+            }
+            _ => None,
+        };
+        let label = match tokens.first() {
+            Some(Token::Label(name, _)) => {
+                let name = name.clone();
+                tokens.remove(0);
+                Some(name)
+            }
+            _ => None,
+        };
+        if let Some(Token::Directive(name, _)) = tokens.first()
+            && name == "rodata" {
+            rodata_phase = true;
+        }
+        let top_level = matches!(tokens.first(), Some(Token::Directive(name, _)) if TOP_LEVEL_DIRECTIVES.contains(&name.as_str()));
+        let statement = statement(source, &tokens);
+
+        match (label, comment) {
+            (None, Some(comment)) if statement.is_empty() => lines.push(Line::Comment(comment)),
+            (None, None) if statement.is_empty() => lines.push(Line::Blank),
+            // code labels get a line of their own
+            (Some(label), comment) if !rodata_phase && !statement.is_empty() => {
+                lines.push(Line::Code { label: Some(label), statement: String::new(), top_level: false, data: false, comment: None });
+                lines.push(Line::Code { label: None, statement, top_level, data: false, comment });
+            }
+            (label, comment) => lines.push(Line::Code { label, statement, top_level, data: rodata_phase, comment }),
+        }
+    }
+    lines
+}
+
+// Tokens joined with canonical spacing
+fn statement(source: &str, tokens: &[&Token]) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&Token> = None;
+    let mut after_unary = false;
+    for token in tokens {
+        let space = match (previous, token) {
+            (None, _) => false,
+            (Some(Token::LeftBracket(_) | Token::LeftParen(_)), _) => false,
+            (Some(_), Token::RightBracket(_) | Token::RightParen(_) | Token::Comma(_)) => false,
+            // the operand of a unary operator follows it directly
+            _ => !after_unary,
+        };
+        if space {
+            text.push(' ');
+        }
+        text += &match token {
+            Token::Directive(name, _) => format!(".{}", name.to_lowercase()),
+            Token::Opcode(_, span) => source[span.clone()].to_lowercase(),
+            token => source[token.span().clone()].to_string(),
+        };
+        after_unary = matches!(token, Token::BinaryOp(Op::Not, _))
+            || matches!(token, Token::BinaryOp(Op::Sub, _)) && !ends_operand(previous);
+        previous = Some(token);
+    }
+    text
+}
+
+// Whether a `-` after this token is a subtraction
+fn ends_operand(token: Option<&Token>) -> bool {
+    matches!(token,
+        Some(Token::Identifier(_, _) | Token::Register(_, _) | Token::ImmediateValue(_, _)
+            | Token::RightBracket(_) | Token::RightParen(_)))
+}
+
+fn render(lines: &[Line]) -> String {
+    let mut output = Vec::new();
+    // alignment is computed per block of consecutive lines
+    for block in lines.split(|line| matches!(line, Line::Blank)) {
+        if block.is_empty() {
+            continue;
+        }
+        let column = block.iter()
+            .filter_map(|line| match line {
+                Line::Code { label: Some(label), statement, .. } if !statement.is_empty() => Some(label.len() + 2),
+                _ => None,
+            })
+            .fold(INDENT, usize::max);
+        let code: Vec<_> = block.iter().map(|line| match line {
+            Line::Code { label, statement, top_level, data, .. } => match label {
+                Some(label) if statement.is_empty() => format!("{}:", label),
+                Some(label) => format!("{:width$}{}", format!("{}:", label), statement, width = column),
+                None if *top_level => statement.clone(),
+                None if *data => format!("{}{}", " ".repeat(column), statement),
+                None => format!("{}{}", " ".repeat(INDENT), statement),
+            },
+            _ => String::new(),
+        }).collect();
+
+        for (idx, line) in block.iter().enumerate() {
+            match line {
+                // standalone comments are indented like the code that follows
+                Line::Comment(comment) => {
+                    let indent = code[idx..].iter().find(|code| !code.is_empty())
+                        .map_or(0, |code| code.len() - code.trim_start().len());
+                    output.push(format!("{}{}", " ".repeat(indent), comment));
+                }
+                Line::Code { comment: Some(comment), .. } => {
+                    // trailing comments line up within a run of commented lines
+                    let run = |range: &mut dyn Iterator<Item = usize>| range
+                        .take_while(|idx| matches!(block[*idx], Line::Code { comment: Some(_), .. }))
+                        .map(|idx| code[idx].chars().count())
+                        .max()
+                        .unwrap_or(0);
+                    let width = run(&mut (idx..block.len())).max(run(&mut (0..=idx).rev()));
+                    output.push(format!("{:width$} {}", code[idx], comment, width = width));
+                }
+                _ => output.push(code[idx].clone()),
+            }
+        }
+        output.push(String::new());
+    }
+    output.pop();
+    let mut text = output.join("\n");
+    text.push('\n');
+    text
+}
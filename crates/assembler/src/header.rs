@@ -28,6 +28,12 @@ pub struct ProgramHeader {
     pub p_align: u64,     // Alignment of segment
 }
 
+impl Default for ElfHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ElfHeader {
     const SOLANA_IDENT: [u8; 16] = [
         0x7f, 0x45, 0x4c, 0x46,  // EI_MAG0..EI_MAG3 ("\x7FELF")
@@ -40,6 +46,7 @@ impl ElfHeader {
     ];
     const SOLANA_TYPE: u16 = 3;      // ET_DYN
     const SOLANA_MACHINE: u16 = 247;  // BPF
+    pub const SBPF_MACHINE: u16 = 263;  // SBPF, required by the strict v3 loader
     const SOLANA_VERSION: u32 = 1;    // EV_CURRENT
     const ELF64_HEADER_SIZE: u16 = 64;
     const PROGRAM_HEADER_SIZE: u16 = 56;
@@ -100,6 +107,10 @@ impl ProgramHeader {
     
     const PAGE_SIZE: u64 = 4096;          // Standard page size
 
+    // Fixed virtual addresses of the SBPF v3 memory regions
+    pub const MM_BYTECODE_START: u64 = 0;
+    pub const MM_RODATA_START: u64 = 1 << 32;
+
     pub fn new_load(offset: u64, size: u64, executable: bool) -> Self {
         let flags = if executable {
             Self::PF_R | Self::PF_X  // Read + Execute
@@ -119,6 +130,20 @@ impl ProgramHeader {
         }
     }
 
+    // v3 segments are mapped at fixed addresses with a single permission each
+    pub fn new_load_at(offset: u64, vaddr: u64, size: u64, executable: bool) -> Self {
+        ProgramHeader {
+            p_type: Self::PT_LOAD,
+            p_flags: if executable { Self::PF_X } else { Self::PF_R },
+            p_offset: offset,
+            p_vaddr: vaddr,
+            p_paddr: vaddr,
+            p_filesz: size,
+            p_memsz: size,
+            p_align: Self::PAGE_SIZE
+        }
+    }
+
     pub fn new_dynamic(offset: u64, size: u64) -> Self {
         ProgramHeader {
             p_type: Self::PT_DYNAMIC,
@@ -165,6 +190,7 @@ impl SectionHeader {
     // Section types
     pub const SHT_NULL: u32 = 0;          // Section header table entry unused
     pub const SHT_PROGBITS: u32 = 1;      // Program data
+    pub const SHT_SYMTAB: u32 = 2;        // Symbol table
     pub const SHT_STRTAB: u32 = 3;        // String table
     pub const SHT_NOBITS: u32 = 8;        // Program space with no data (bss)
     pub const SHT_DYNAMIC: u32 = 6;      // Dynamic section
@@ -176,6 +202,7 @@ impl SectionHeader {
     pub const SHF_ALLOC: u64 = 0x2;       // Occupies memory during execution
     pub const SHF_EXECINSTR: u64 = 0x4;   // Executable
     
+    #[allow(clippy::too_many_arguments)]
     pub fn new(name_offset: u32, sh_type: u32, flags: u64, addr: u64, offset: u64, size: u64, link: u32, info: u32, addralign: u64, entsize: u64) -> Self {
         Self {
             sh_name: name_offset,
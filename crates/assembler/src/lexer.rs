@@ -1,40 +1,79 @@
 use crate::opcode::Opcode;
 use crate::errors::CompileError;
+use crate::expression::Expr;
+use std::iter::Peekable;
 use std::ops::Range;
+use std::str::CharIndices;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Op {
     Add,
     Sub,
+    Mul,
+    Div,
+    Mod,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    // unary only
+    Not,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ImmediateValue {
     Int(i64),
     Addr(i64),
+    // literals past i64::MAX, only valid where all 64 bits are encoded
+    UInt(u64),
+}
+
+impl ImmediateValue {
+    // bit pattern of the value, as encoded in the instruction
+    pub fn to_i64(&self) -> i64 {
+        match self {
+            ImmediateValue::Int(val) | ImmediateValue::Addr(val) => *val,
+            ImmediateValue::UInt(val) => *val as i64,
+        }
+    }
+
+    pub fn to_i128(&self) -> i128 {
+        match self {
+            ImmediateValue::Int(val) | ImmediateValue::Addr(val) => *val as i128,
+            ImmediateValue::UInt(val) => *val as i128,
+        }
+    }
+
+    // non-negative values only become unsigned once they don't fit in an i64
+    pub fn from_i128(value: i128, is_addr: bool) -> ImmediateValue {
+        if is_addr {
+            ImmediateValue::Addr(value as i64)
+        } else if value > i64::MAX as i128 {
+            ImmediateValue::UInt(value as u64)
+        } else {
+            ImmediateValue::Int(value as i64)
+        }
+    }
+
+    pub fn is_addr(&self) -> bool {
+        matches!(self, ImmediateValue::Addr(_))
+    }
 }
 
 impl std::ops::Add for ImmediateValue {
     type Output = ImmediateValue;
     fn add(self, other: Self) -> ImmediateValue {
-        match (self, other) {
-            (ImmediateValue::Int(a), ImmediateValue::Int(b)) => ImmediateValue::Int(a + b),
-            (ImmediateValue::Addr(a), ImmediateValue::Addr(b)) => ImmediateValue::Addr(a + b),
-            (ImmediateValue::Int(a), ImmediateValue::Addr(b)) => ImmediateValue::Addr(a + b),
-            (ImmediateValue::Addr(a), ImmediateValue::Int(b)) => ImmediateValue::Addr(a + b),
-        }
+        let is_addr = self.is_addr() || other.is_addr();
+        ImmediateValue::from_i128(self.to_i128() + other.to_i128(), is_addr)
     }
 }
 
 impl std::ops::Sub for ImmediateValue {
     type Output = ImmediateValue;
     fn sub(self, other: Self) -> ImmediateValue {
-        match (self, other) {
-            (ImmediateValue::Int(a), ImmediateValue::Int(b)) => ImmediateValue::Int(a - b),
-            (ImmediateValue::Addr(a), ImmediateValue::Addr(b)) => ImmediateValue::Addr(a - b),
-            (ImmediateValue::Int(a), ImmediateValue::Addr(b)) => ImmediateValue::Addr(a - b),
-            (ImmediateValue::Addr(a), ImmediateValue::Int(b)) => ImmediateValue::Addr(a - b),
-        }
+        let is_addr = self.is_addr() || other.is_addr();
+        ImmediateValue::from_i128(self.to_i128() - other.to_i128(), is_addr)
     }
 }
 
@@ -47,17 +86,103 @@ pub enum Token {
     Register(u8, Range<usize>),
     ImmediateValue(ImmediateValue, Range<usize>),
     BinaryOp(Op, Range<usize>),
-    StringLiteral(String, Range<usize>),
+    // escapes are decoded, so the bytes are not necessarily valid utf-8
+    StringLiteral(Vec<u8>, Range<usize>),
+    // never lexed, an operand that depends on labels or `.` and is
+    // evaluated by the parser once every label is known
+    Expression(Expr, Range<usize>),
 
     LeftBracket(Range<usize>),
     RightBracket(Range<usize>),
+    LeftParen(Range<usize>),
+    RightParen(Range<usize>),
     Comma(Range<usize>),
     Colon(Range<usize>),
 
     Newline(Range<usize>),
+    // where the lexer reported an error
+    Error(Range<usize>),
+    // trivia, kept for the formatter and dropped by the parser. The text
+    // includes the `#` or `//`
+    Comment(String, Range<usize>),
+}
+
+impl Token {
+    pub fn span(&self) -> &Range<usize> {
+        match self {
+            Token::Directive(_, span)
+            | Token::Label(_, span)
+            | Token::Identifier(_, span)
+            | Token::Opcode(_, span)
+            | Token::Register(_, span)
+            | Token::ImmediateValue(_, span)
+            | Token::BinaryOp(_, span)
+            | Token::StringLiteral(_, span)
+            | Token::Expression(_, span)
+            | Token::LeftBracket(span)
+            | Token::RightBracket(span)
+            | Token::LeftParen(span)
+            | Token::RightParen(span)
+            | Token::Comma(span)
+            | Token::Colon(span)
+            | Token::Newline(span)
+            | Token::Error(span)
+            | Token::Comment(_, span) => span,
+        }
+    }
 }
 
-pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
+// Standard assembler escapes, `chars` is right past the backslash.
+// Returns the offending escape on failure
+fn unescape(chars: &mut Peekable<CharIndices<'_>>) -> Result<u8, String> {
+    let Some((_, c)) = chars.next() else {
+        return Err("\\".to_string());
+    };
+    match c {
+        'n' => Ok(b'\n'),
+        't' => Ok(b'\t'),
+        'r' => Ok(b'\r'),
+        '0' => Ok(0),
+        '\\' | '"' | '\'' => Ok(c as u8),
+        'x' => {
+            // one or two hex digits
+            let mut digits = String::new();
+            while digits.len() < 2 && let Some((_, d)) = chars.peek() && d.is_ascii_hexdigit() {
+                digits.push(*d);
+                chars.next();
+            }
+            u8::from_str_radix(&digits, 16).map_err(|_| "\\x".to_string())
+        }
+        c => Err(format!("\\{}", c)),
+    }
+}
+
+// Decimal, 0x hex, 0b binary and 0o octal literals with optional `_` separators.
+// Hex literals are treated as addresses
+fn parse_number(number: &str) -> Result<ImmediateValue, &'static str> {
+    let lower = number.to_ascii_lowercase();
+    let (digits, radix) = match lower.get(..2) {
+        Some("0x") => (&lower[2..], 16),
+        Some("0b") => (&lower[2..], 2),
+        Some("0o") => (&lower[2..], 8),
+        _ => (lower.as_str(), 10),
+    };
+    let digits = digits.replace('_', "");
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(match radix {
+            16 => "expects hexadecimal digits",
+            2 => "expects binary digits",
+            8 => "expects octal digits",
+            _ => "expects decimal digits",
+        });
+    }
+    let value = u64::from_str_radix(&digits, radix).map_err(|_| "does not fit in 64 bits")?;
+    Ok(ImmediateValue::from_i128(value as i128, radix == 16 && value <= i64::MAX as u64))
+}
+
+// Errors are returned along with the tokens, each one is also left in the
+// stream as a `Token::Error` so the parser can skip its line
+pub fn tokenize(source: &str) -> (Vec<Token>, Vec<CompileError>) {
     let mut tokens = Vec::new();
     let mut errors = Vec::new();
     let mut byte_offset = 0;
@@ -73,33 +198,18 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
             match c {
                 c if c.is_ascii_digit() => {
                     let mut number = String::new();
-                    let mut is_addr = false;
                     while let Some((_, c)) = chars.peek() {
-                        if c.is_digit(10) {
-                            number.push(chars.next().unwrap().1);
-                        } else if number == "0" && *c == 'x' {
-                            chars.next();
-                            is_addr = true; /*  */ number = String::new();
-                        } else if is_addr && (*c == 'a' || *c == 'b' || *c == 'c' || *c == 'd' || *c == 'e' || *c == 'f') {
+                        if c.is_ascii_alphanumeric() || *c == '_' {
                             number.push(chars.next().unwrap().1);
                         } else {
                             break;
                         }
                     }
                     let span = token_start..token_start + number.len();
-                    if is_addr {
-                        if let Ok(value) = i64::from_str_radix(&number, 16) {
-                            tokens.push(Token::ImmediateValue(ImmediateValue::Addr(value), span.clone()));
-                        } else {
-                            errors.push(CompileError::InvalidNumber { number, span: span.clone(), custom_label: None });
-                        }
-                    } else {
-                        if let Ok(value) = number.parse::<i64>() {
-                            tokens.push(Token::ImmediateValue(ImmediateValue::Int(value), span.clone()));
-                        } else {
-                            errors.push(CompileError::InvalidNumber { number, span: span.clone(), custom_label: None });
-                        }
-                    }      
+                    match parse_number(&number) {
+                        Ok(value) => tokens.push(Token::ImmediateValue(value, span)),
+                        Err(label) => lex_error(&mut tokens, &mut errors, CompileError::InvalidNumber { number, span, custom_label: Some(label.to_string()) }),
+                    }
                 }
 
                 c if c.is_ascii_alphanumeric() || *c == '_' => {
@@ -120,7 +230,7 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
                         if let Ok(value) = identifier[1..].parse::<u8>() {
                             tokens.push(Token::Register(value, span.clone()));
                         } else {
-                            errors.push(CompileError::InvalidRegister { register: identifier, span: span.clone(), custom_label: None });
+                            lex_error(&mut tokens, &mut errors, CompileError::InvalidRegister { register: identifier, span: span.clone(), custom_label: None });
                         }
                     } else if let Ok(opcode) = Opcode::from_str(&identifier) {
                         tokens.push(Token::Opcode(opcode, span));
@@ -128,6 +238,20 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
                         tokens.push(Token::Identifier(identifier, span));
                     }
                 }
+                // symbol types, e.g. `.type entrypoint, @function`
+                '@' => {
+                    chars.next();
+                    let mut identifier = String::from("@");
+                    while let Some((_, c)) = chars.peek() {
+                        if c.is_ascii_alphanumeric() || *c == '_' {
+                            identifier.push(chars.next().unwrap().1);
+                        } else {
+                            break;
+                        }
+                    }
+                    let span = token_start..token_start + identifier.len();
+                    tokens.push(Token::Identifier(identifier, span));
+                }
                 c if c.is_whitespace() => {
                     chars.next();
                 }
@@ -143,26 +267,72 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
                 }
                 '.' => {
                     chars.next();
-                    let directive: String = chars.by_ref()
-                        .take_while(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
-                        .map(|(_, c)| c)
-                        .collect();
+                    let mut directive = String::new();
+                    while let Some((_, c)) = chars.peek() {
+                        if c.is_ascii_alphanumeric() || *c == '_' {
+                            directive.push(chars.next().unwrap().1);
+                        } else {
+                            break;
+                        }
+                    }
                     let span = token_start..token_start + directive.len() + 1;
-                    tokens.push(Token::Directive(directive, span));
+                    if directive.is_empty() {
+                        // a lone `.` is the location counter
+                        tokens.push(Token::Identifier(".".to_string(), span));
+                    } else {
+                        tokens.push(Token::Directive(directive, span));
+                    }
                 }
                 '"' => {
                     chars.next();
-                    let mut string_literal = String::new();
-                    while let Some((_, c)) = chars.peek() {
-                        if *c == '"' {
+                    let mut string_literal = Vec::new();
+                    let mut terminated = false;
+                    while let Some((idx, c)) = chars.next() {
+                        match c {
+                            '"' => {
+                                let span = token_start..byte_offset + idx + 1;
+                                tokens.push(Token::StringLiteral(std::mem::take(&mut string_literal), span));
+                                terminated = true;
+                                break;
+                            }
+                            '\\' => match unescape(&mut chars) {
+                                Ok(byte) => string_literal.push(byte),
+                                Err(escape) => {
+                                    let end = chars.peek().map_or(line.len(), |(i, _)| *i);
+                                    lex_error(&mut tokens, &mut errors, CompileError::InvalidEscapeSequence { escape, span: byte_offset + idx..byte_offset + end, custom_label: None });
+                                }
+                            },
+                            c => string_literal.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                        }
+                    }
+                    // strings can't span lines, point from the opening quote to the end of the line
+                    if !terminated {
+                        lex_error(&mut tokens, &mut errors, CompileError::UnterminatedStringLiteral { span: token_start..byte_offset + line.len(), custom_label: None });
+                    }
+                }
+                // character literals are immediates, e.g. `mov64 r1, 'A'`
+                '\'' => {
+                    chars.next();
+                    let value = match chars.next() {
+                        Some((idx, '\\')) => unescape(&mut chars).map_err(|escape| {
+                            let end = chars.peek().map_or(line.len(), |(i, _)| *i);
+                            CompileError::InvalidEscapeSequence { escape, span: byte_offset + idx..byte_offset + end, custom_label: None }
+                        }),
+                        Some((_, c)) if c.is_ascii() && c != '\'' => Ok(c as u8),
+                        _ => Err(CompileError::InvalidCharLiteral { span: token_start..token_start + 1, custom_label: None }),
+                    };
+                    match (value, chars.peek()) {
+                        (Ok(value), Some((idx, '\''))) => {
+                            let span = token_start..byte_offset + idx + 1;
                             chars.next();
-                            let span = token_start..token_start + string_literal.len() + 2;
-                            tokens.push(Token::StringLiteral(string_literal, span));
-                            break;
-                        } else if *c == '\n' {
-                            errors.push(CompileError::UnterminatedStringLiteral { span: token_start..token_start + 1, custom_label: None });
+                            tokens.push(Token::ImmediateValue(ImmediateValue::Int(value as i64), span));
+                        }
+                        (Err(e), _) => lex_error(&mut tokens, &mut errors, e),
+                        (Ok(_), _) => {
+                            // report the whole literal up to its closing quote, if any
+                            let end = chars.by_ref().find(|(_, c)| *c == '\'').map_or(line.len(), |(i, _)| i + 1);
+                            lex_error(&mut tokens, &mut errors, CompileError::InvalidCharLiteral { span: token_start..byte_offset + end, custom_label: None });
                         }
-                        string_literal.push(chars.next().unwrap().1);
                     }
                 }
                 '[' => {
@@ -180,24 +350,60 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
                     let span = token_start..token_start + 1;
                     tokens.push(Token::Comma(span));
                 }
-                // handle comments
+                // comments run to the end of the line
                 '#' => {
-                    chars.next();
+                    let text = line[*start_idx..].trim_end().to_string();
+                    tokens.push(Token::Comment(text, token_start..byte_offset + line.trim_end().len()));
                     break;
                 }
                 '/' => {
+                    let comment_start = *start_idx;
                     chars.next();
                     if let Some((_, '/')) = chars.peek() {
-                        chars.next();
+                        let text = line[comment_start..].trim_end().to_string();
+                        tokens.push(Token::Comment(text, token_start..byte_offset + line.trim_end().len()));
                         break;
                     } else {
                         let span = token_start..token_start + 1;
-                        errors.push(CompileError::UnexpectedCharacter { character: '/', span, custom_label: None });
+                        tokens.push(Token::BinaryOp(Op::Div, span));
+                    }
+                }
+                '*' | '%' | '&' | '|' | '^' | '~' => {
+                    let op = match chars.next().unwrap().1 {
+                        '*' => Op::Mul,
+                        '%' => Op::Mod,
+                        '&' => Op::And,
+                        '|' => Op::Or,
+                        '^' => Op::Xor,
+                        _ => Op::Not,
+                    };
+                    let span = token_start..token_start + 1;
+                    tokens.push(Token::BinaryOp(op, span));
+                }
+                '<' | '>' => {
+                    let c = chars.next().unwrap().1;
+                    if let Some((_, next)) = chars.peek() && *next == c {
+                        chars.next();
+                        let op = if c == '<' { Op::Shl } else { Op::Shr };
+                        tokens.push(Token::BinaryOp(op, token_start..token_start + 2));
+                    } else {
+                        let span = token_start..token_start + 1;
+                        lex_error(&mut tokens, &mut errors, CompileError::UnexpectedCharacter { character: c, span, custom_label: None });
                     }
                 }
+                '(' => {
+                    chars.next();
+                    let span = token_start..token_start + 1;
+                    tokens.push(Token::LeftParen(span));
+                }
+                ')' => {
+                    chars.next();
+                    let span = token_start..token_start + 1;
+                    tokens.push(Token::RightParen(span));
+                }
                 _ => {
                     let span = token_start..token_start + 1;
-                    errors.push(CompileError::UnexpectedCharacter { character: *c, span, custom_label: None });
+                    lex_error(&mut tokens, &mut errors, CompileError::UnexpectedCharacter { character: *c, span, custom_label: None });
                     chars.next();
                 }
             }
@@ -206,9 +412,10 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
         tokens.push(Token::Newline(byte_offset..byte_offset + 1));
         byte_offset += 1;
     }
-    if errors.is_empty() {
-        Ok(tokens)
-    } else {
-        Err(errors)
-    }
+    (tokens, errors)
+}
+
+fn lex_error(tokens: &mut Vec<Token>, errors: &mut Vec<CompileError>, error: CompileError) {
+    tokens.push(Token::Error(error.span().clone()));
+    errors.push(error);
 }
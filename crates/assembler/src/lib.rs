@@ -4,21 +4,38 @@ extern crate anyhow;
 
 use std::path::Path;
 use anyhow::{Error, Result};
+use codespan_reporting::diagnostic::Diagnostic;
 use codespan_reporting::files::SimpleFile;
 use codespan_reporting::term;
 use codespan_reporting::term::{Config};
 use termcolor::{ColorChoice, StandardStream};
-use crate::errors::AsDiagnostic;
+use crate::errors::{AsDiagnostic, CompileError};
+use crate::parser::ParseResult;
+use crate::warnings::{LintLevel, LintPolicy};
+use crate::message_format::MessageFormat;
 
 // Tokenizer and parser
 pub mod parser;
 pub mod lexer;
+pub mod expression;
 pub mod opcode;
 
+// Target SBPF version and syscalls
+pub mod version;
+pub mod syscalls;
+
 // Error handling and diagnostics
 pub mod macros;
 pub mod errors;
 pub mod messages;
+pub mod warnings;
+pub mod suggest;
+pub mod message_format;
+pub mod explain;
+
+// Editor support
+pub mod analysis;
+pub mod format;
 
 // Intermediate Representation
 pub mod astnode;
@@ -29,6 +46,10 @@ pub mod header;
 pub mod program;
 pub mod section;
 
+// Reading programs back
+pub mod elf;
+pub mod disasm;
+
 // Debug info
 pub mod debuginfo;
 
@@ -44,40 +65,72 @@ pub use self::{
     parser::Parser,
     program::Program,
     lexer::tokenize,
+    version::SbpfVersion,
 };
 
-pub fn assemble(src: &str, deploy: &str) -> Result<()> {
+#[derive(Debug, Clone, Default)]
+pub struct AssembleOptions {
+    pub version: SbpfVersion,
+    pub lints: LintPolicy,
+    pub message_format: MessageFormat,
+}
+
+// Lexes and parses the file. The parser runs even when lexing fails, the
+// errors of both phases are returned together in source order
+pub fn parse_source(file: &SimpleFile<String, String>, version: SbpfVersion) -> Result<ParseResult, Vec<CompileError>> {
+    let (tokens, mut errors) = tokenize(file.source());
+    match Parser::new(tokens, file).with_version(version).parse() {
+        Ok(parse_result) if errors.is_empty() => return Ok(parse_result),
+        Ok(_) => {}
+        Err(parse_errors) => errors.extend(parse_errors),
+    }
+    errors.sort_by_key(|error| error.span().start);
+    Err(errors)
+}
+
+pub fn assemble(src: &str, deploy: &str, options: &AssembleOptions) -> Result<()> {
     let source_code = std::fs::read_to_string(src)?;
     let file = SimpleFile::new(src.to_string(), source_code.clone());
 
-    // TODO: ideally we should have only collect errors and then print them with parsers
-    // errors all at once
-    let tokens = match tokenize(&source_code) {
-        Ok(tokens) => tokens,
-        Err(errors) => {
-            for error in errors {
-                let writer = StandardStream::stderr(ColorChoice::Auto);
-                let config = Config::default();
-                let diagnostic = error.to_diagnostic();
-                term::emit(&mut writer.lock(), &config, &file, &diagnostic)?;
-            }
-            return Err(Error::msg("Compilation failed"));
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let config = Config::default();
+    let emit = |diagnostic: Diagnostic<()>| -> Result<()> {
+        match options.message_format {
+            MessageFormat::Human => term::emit(&mut writer.lock(), &config, &file, &diagnostic)?,
+            MessageFormat::Json => println!("{}", message_format::to_json(&diagnostic, &file)),
         }
+        Ok(())
     };
-    let mut parser = Parser::new(tokens, &file);
-    let parse_result = match parser.parse() {
-        Ok(program) => program,
+    let failed = |count: usize| {
+        let plural = if count == 1 { "" } else { "s" };
+        Err(Error::msg(format!(
+            "Compilation failed due to {} previous error{}\nFor more information about an error, try `sbpf explain <code>`",
+            count, plural)))
+    };
+    let parse_result = match parse_source(&file, options.version) {
+        Ok(parse_result) => parse_result,
         Err(errors) => {
-            for error in errors {
-                let writer = StandardStream::stderr(ColorChoice::Auto);
-                let config = Config::default();
-                let diagnostic = error.to_diagnostic();
-                term::emit(&mut writer.lock(), &config, &file, &diagnostic)?;
+            for error in &errors {
+                emit(error.to_diagnostic())?;
             }
-            return Err(Error::msg("Compilation failed"));
+            return failed(errors.len());
         }
     };
 
+    let mut denied = 0;
+    for warning in &parse_result.warnings {
+        let level = options.lints.level(warning.lint());
+        if level != LintLevel::Allow {
+            emit(warning.to_diagnostic(level))?;
+        }
+        if level == LintLevel::Deny {
+            denied += 1;
+        }
+    }
+    if denied > 0 {
+        return failed(denied);
+    }
+
     let program = Program::from_parse_result(parse_result);
 
     let bytecode = program.emit_bytecode();
@@ -93,3 +146,28 @@ pub fn assemble(src: &str, deploy: &str) -> Result<()> {
     std::fs::write(output_path, bytecode)?;
     Ok(())
 }
+
+// Rewrites the file in canonical layout. With `check` the file is left as is,
+// either way returns whether it changes. Syntax errors are reported like in
+// `assemble`
+pub fn format_file(src: &str, check: bool) -> Result<bool> {
+    let source_code = std::fs::read_to_string(src)?;
+    match format::format(&source_code) {
+        Ok(formatted) => {
+            let changed = formatted != source_code;
+            if changed && !check {
+                std::fs::write(src, formatted)?;
+            }
+            Ok(changed)
+        }
+        Err(errors) => {
+            let file = SimpleFile::new(src.to_string(), source_code);
+            let writer = StandardStream::stderr(ColorChoice::Auto);
+            for error in &errors {
+                term::emit(&mut writer.lock(), &Config::default(), &file, &error.to_diagnostic())?;
+            }
+            let plural = if errors.len() == 1 { "" } else { "s" };
+            Err(Error::msg(format!("Can't format {} due to {} syntax error{}", src, errors.len(), plural)))
+        }
+    }
+}
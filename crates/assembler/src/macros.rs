@@ -3,6 +3,7 @@ macro_rules! define_compile_errors {
     (
         $(
             $variant:ident {
+                code = $code:literal,
                 error = $error_msg:literal,
                 label = $label_msg:literal,
                 fields = { $( $field_name:ident : $field_ty:ty ),* $(,)? }
@@ -17,7 +18,83 @@ macro_rules! define_compile_errors {
             ),*
         }
 
+        // every error code, for `sbpf explain`
+        pub const ERROR_CODES: &[&str] = &[$( $code ),*];
+
         impl CompileError {
+            // stable across releases, a retired code is never reused
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(
+                        Self::$variant { .. } => $code,
+                    )*
+                }
+            }
+
+            pub fn label(&self) -> &str {
+                match self {
+                    $(
+                        Self::$variant { custom_label, .. } => custom_label.as_deref().unwrap_or($label_msg),
+                    )*
+                }
+            }
+
+            pub fn span(&self) -> &Range<usize> {
+                match self {
+                    $(
+                        Self::$variant { span, .. } => span,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+// Same shape as the errors, each warning also names the lint that can
+// allow or deny it
+#[macro_export]
+macro_rules! define_compile_warnings {
+    (
+        $(
+            $variant:ident {
+                code = $code:literal,
+                lint = $lint:literal,
+                warning = $warning_msg:literal,
+                label = $label_msg:literal,
+                fields = { $( $field_name:ident : $field_ty:ty ),* $(,)? }
+            }
+        ),* $(,)?
+    ) => {
+        #[derive(Debug, thiserror::Error)]
+        pub enum CompileWarning {
+            $(
+                #[error($warning_msg)]
+                $variant { $( $field_name: $field_ty ),*, custom_label: Option<String> }
+            ),*
+        }
+
+        // every lint name, for validating command line flags
+        pub const LINTS: &[&str] = &[$( $lint ),*];
+
+        pub const WARNING_CODES: &[&str] = &[$( $code ),*];
+
+        impl CompileWarning {
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(
+                        Self::$variant { .. } => $code,
+                    )*
+                }
+            }
+
+            pub fn lint(&self) -> &'static str {
+                match self {
+                    $(
+                        Self::$variant { .. } => $lint,
+                    )*
+                }
+            }
+
             pub fn label(&self) -> &str {
                 match self {
                     $(
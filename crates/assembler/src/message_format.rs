@@ -0,0 +1,78 @@
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle, Severity};
+use codespan_reporting::files::{Files, SimpleFile};
+use codespan_reporting::term;
+use codespan_reporting::term::Config;
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+use termcolor::NoColor;
+
+// How diagnostics are printed, colored text on stderr or one JSON object
+// per line on stdout for editors and CI
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("unknown message format '{}', expected human or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for MessageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageFormat::Human => write!(f, "human"),
+            MessageFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+// Spans carry byte offsets along with 1-based lines and columns, the end
+// is exclusive. `rendered` is the human readable form without colors
+pub fn to_json(diagnostic: &Diagnostic<()>, file: &SimpleFile<String, String>) -> Value {
+    let severity = match diagnostic.severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    };
+    let labels: Vec<Value> = diagnostic.labels.iter().map(|label| {
+        let start = file.location((), label.range.start).ok();
+        let end = file.location((), label.range.end).ok();
+        json!({
+            "primary": label.style == LabelStyle::Primary,
+            "message": label.message,
+            "byte_start": label.range.start,
+            "byte_end": label.range.end,
+            "line_start": start.as_ref().map(|location| location.line_number),
+            "column_start": start.as_ref().map(|location| location.column_number),
+            "line_end": end.as_ref().map(|location| location.line_number),
+            "column_end": end.as_ref().map(|location| location.column_number),
+        })
+    }).collect();
+    let mut rendered = NoColor::new(Vec::new());
+    let rendered = match term::emit(&mut rendered, &Config::default(), file, diagnostic) {
+        Ok(()) => String::from_utf8_lossy(&rendered.into_inner()).into_owned(),
+        Err(_) => String::new(),
+    };
+    json!({
+        "severity": severity,
+        "code": diagnostic.code,
+        "message": diagnostic.message,
+        "file": file.name(),
+        "labels": labels,
+        "notes": diagnostic.notes,
+        "rendered": rendered,
+    })
+}
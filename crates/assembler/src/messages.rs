@@ -5,13 +5,45 @@
 
 // we can do a more fine-grained error message by pointing out the exact token that caused the error
 // with a special error pattern parser, but for now we just provide expected patterns
-pub const EXPECTS_LABEL_DIR_STR: &str = "expects <label>, <directive>, <string literal>";
+pub const EXPECTS_COMMA_SEPARATED_VALUES: &str = "expects comma separated values";
+pub const EXPECTS_STR_LIST: &str = "expects <string literal>, ...";
+pub const EXPECTS_IMM_LIST: &str = "expects <immediate value>, ...";
+pub const EXPECTS_IMM_OR_IDEN_LIST: &str = "expects <immediate value>/<identifier>, ...";
+pub const EXPECTS_COUNT: &str = "expects <size>";
+pub const EXPECTS_COUNT_FILL: &str = "expects <size>[, <fill>]";
+pub const EXPECTS_REPEAT_SIZE_VALUE: &str = "expects <repeat>[, <size>[, <value>]]";
+pub const EXPECTS_FILL_SIZE: &str = "expects a <size> between 1 and 8";
+pub const EXPECTS_ALIGN_FILL: &str = "expects <alignment>[, <fill>]";
+pub const EXPECTS_P2ALIGN_FILL: &str = "expects <power of two>[, <fill>]";
+pub const EXPECTS_POW2_ALIGN: &str = "expects a power of two alignment up to 4096";
+pub const EXPECTS_CLOSING_PAREN: &str = "expects a closing `)`";
+pub const EXPECTS_SHIFT_AMOUNT: &str = "expects a shift amount between 0 and 63";
+pub const EXPECTS_SHIFT_AMOUNT_32: &str = "expects a shift amount between 0 and 31";
+pub const EXPECTS_SWAP_WIDTH: &str = "expects a width of 16, 32 or 64";
+pub const EXPECTS_IMM32: &str = "expects a 32 bit immediate";
+pub const EXPECTS_SIGNED_IMM32: &str = "expects a signed 32 bit immediate, it is sign extended to 64 bits. Use lddw to load a 64 bit constant";
+pub const EXPECTS_NEGATABLE_IMM32: &str = "expects an immediate above -2147483648, v2 subtracts it by adding its negation";
+pub const EXPECTS_OFFSET16: &str = "expects a signed 16 bit offset";
+pub const EXPECTS_CONSTANT: &str = "expects a constant expression";
+pub const EXPECTS_CONSTANT_NOT_ADDRESS: &str = "expects a constant, e.g. the distance between two labels";
+pub const EXPECTS_NO_TEXT_FILL: &str = ".text is padded with `ja +0`, a fill value is only allowed in .rodata";
 pub const EXPECTS_IDEN: &str = "expects <identifier>";
+pub const EXPECTS_ONE_ENTRY: &str = "the entry point is already set by an earlier .entry";
+pub const EXPECTS_FUNCTION_OR_SYSCALL: &str = "expects a label in .text, a syscall or a name declared with .extern";
+pub const EXPECTS_IDEN_COM_FUNCTION: &str = "expects <identifier>, @function";
 pub const EXPECTS_IDEN_COM_IMM: &str = "expects <identifier>, <immediate value>";
+pub const EXPECTS_REG: &str = "expects <register>";
+pub const EXPECTS_REG_R0_R10: &str = "expects a register between r0 and r10";
+pub const EXPECTS_ADD64_TO_ADJUST_R10: &str = "r10 is the frame pointer, only `add64 r10, <immediate value>` can adjust it";
+pub const EXPECTS_STATEMENT: &str = "expects an instruction, a label or a directive";
+pub const EXPECTS_ONE_INSTRUCTION_PER_LINE: &str = "expects one instruction per line";
 pub const EXPECTS_MORE_OPERAND: &str = "expects more operand";
 pub const EXPECTS_REG_COM_IMM: &str = "expects <register>, <immediate value>";
 pub const EXPECTS_REG_COM_REG: &str = "expects <register>, <register>";
 pub const EXPECTS_REG_COM_IMM_OR_IDEN: &str = "expects <register>, <immediate value>/<identifier>";
 pub const EXPECTS_REG_COM_IMM_COM_IMM_OR_IDEN: &str = "expects <register>, <immediate value>, <immediate value>/<identifier>";
 pub const EXPECTS_REG_COM_LB_REG_BIOP_IMM_RB: &str = "expects <register>, [<register> <binary operator> <immediate value>]";
-pub const EXPECTS_LB_REG_BIOP_IMM_RB_COM_REG: &str = "expects [<register> <binary operator> <immediate value>], <register>";
\ No newline at end of file
+pub const EXPECTS_LB_REG_BIOP_IMM_RB_COM_REG: &str = "expects [<register> <binary operator> <immediate value>], <register>";
+pub const FIRST_DEFINED_HERE: &str = "first defined here";
+pub const ALREADY_DEFINED_AS_LABEL: &str = "already defined as a label";
+pub const ALREADY_DEFINED_AS_CONSTANT: &str = "already defined as a constant";
@@ -1,4 +1,6 @@
 use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use crate::version::SbpfVersion;
 
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
 #[repr(u8)]
@@ -169,6 +171,7 @@ pub enum Opcode {
 }
 
 impl Opcode {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self, &'static str> {
         match s.to_lowercase().as_str() {
             "lddw" => Ok(Opcode::Lddw),
@@ -366,6 +369,50 @@ impl Opcode {
         }
     }
 
+    // The reverse of `to_bytecode_for`
+    pub fn from_bytecode_for(u: u8, version: SbpfVersion) -> Option<Self> {
+        if !version.move_memory_instruction_classes() {
+            return Self::from_u8(u);
+        }
+        match u {
+            0x2c => Some(Opcode::Ldxb),
+            0x3c => Some(Opcode::Ldxh),
+            0x8c => Some(Opcode::Ldxw),
+            0x9c => Some(Opcode::Ldxdw),
+            0x27 => Some(Opcode::Stb),
+            0x37 => Some(Opcode::Sth),
+            0x87 => Some(Opcode::Stw),
+            0x97 => Some(Opcode::Stdw),
+            0x2f => Some(Opcode::Stxb),
+            0x3f => Some(Opcode::Stxh),
+            0x8f => Some(Opcode::Stxw),
+            0x9f => Some(Opcode::Stxdw),
+            _ => Self::from_u8(u),
+        }
+    }
+
+    // v2 moves loads and stores into the classes freed up by the pqr instructions
+    pub fn to_bytecode_for(&self, version: SbpfVersion) -> u8 {
+        if !version.move_memory_instruction_classes() {
+            return self.to_bytecode();
+        }
+        match self {
+            Opcode::Ldxb => 0x2c,
+            Opcode::Ldxh => 0x3c,
+            Opcode::Ldxw => 0x8c,
+            Opcode::Ldxdw => 0x9c,
+            Opcode::Stb => 0x27,
+            Opcode::Sth => 0x37,
+            Opcode::Stw => 0x87,
+            Opcode::Stdw => 0x97,
+            Opcode::Stxb => 0x2f,
+            Opcode::Stxh => 0x3f,
+            Opcode::Stxw => 0x8f,
+            Opcode::Stxdw => 0x9f,
+            _ => self.to_bytecode(),
+        }
+    }
+
     pub fn to_bytecode(&self) -> u8 {
         match self {
             Opcode::Lddw => 0x18,
@@ -593,7 +640,8 @@ impl Opcode {
             Opcode::Shmul64 | Opcode::Shmul64Imm | Opcode::Shmul64Reg => "shmul64",
             Opcode::Sdiv64 | Opcode::Sdiv64Imm | Opcode::Sdiv64Reg => "sdiv64",
             Opcode::Srem64 | Opcode::Srem64Imm | Opcode::Srem64Reg => "srem64",
-            Opcode::Ja | Opcode::Jeq | Opcode::JeqImm | Opcode::JeqReg => "jeq",
+            Opcode::Ja => "ja",
+            Opcode::Jeq | Opcode::JeqImm | Opcode::JeqReg => "jeq",
             Opcode::Jgt | Opcode::JgtImm | Opcode::JgtReg => "jgt",
             Opcode::Jge | Opcode::JgeImm | Opcode::JgeReg => "jge",
             Opcode::Jlt | Opcode::JltImm | Opcode::JltReg => "jlt",
@@ -604,11 +652,150 @@ impl Opcode {
             Opcode::Jsge | Opcode::JsgeImm | Opcode::JsgeReg => "jsge",
             Opcode::Jslt | Opcode::JsltImm | Opcode::JsltReg => "jslt",
             Opcode::Jsle | Opcode::JsleImm | Opcode::JsleReg => "jsle",
-            Opcode::Call | Opcode::Callx => "call",
+            Opcode::Call => "call",
+            Opcode::Callx => "callx",
             Opcode::Exit => "exit",
         }
     }
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         self.to_str().to_string()
     }
+
+    // Branches with a 16 bit pc-relative offset, counted in instructions
+    pub fn is_jump(&self) -> bool {
+        matches!(self,
+            Opcode::Ja
+            | Opcode::JeqImm | Opcode::JgtImm | Opcode::JgeImm | Opcode::JltImm
+            | Opcode::JleImm | Opcode::JsetImm | Opcode::JneImm | Opcode::JsgtImm
+            | Opcode::JsgeImm | Opcode::JsltImm | Opcode::JsleImm
+            | Opcode::JeqReg | Opcode::JgtReg | Opcode::JgeReg | Opcode::JltReg
+            | Opcode::JleReg | Opcode::JsetReg | Opcode::JneReg | Opcode::JsgtReg
+            | Opcode::JsgeReg | Opcode::JsltReg | Opcode::JsleReg)
+    }
+
+    // Every mnemonic accepted in source, in opcode order
+    pub fn mnemonics() -> impl Iterator<Item = &'static str> {
+        let mut previous = None;
+        (0..=Opcode::Exit as u8)
+            .filter_map(<Opcode as FromPrimitive>::from_u8)
+            .map(|opcode| opcode.to_str())
+            .filter(move |mnemonic| previous.replace(*mnemonic) != Some(*mnemonic))
+    }
+
+    // Operands as written after the mnemonic, one entry per accepted form
+    pub fn operand_forms(&self) -> &'static [&'static str] {
+        match self {
+            Opcode::Lddw => &["<register>, <immediate value>", "<register>, <label>"],
+            Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw => &["<register>, [<register> + <offset>]"],
+            Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw
+            | Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => &["[<register> + <offset>], <register>"],
+            Opcode::Neg32 | Opcode::Neg64 | Opcode::Callx => &["<register>"],
+            Opcode::Hor64Imm => &["<register>, <immediate value>"],
+            Opcode::Le | Opcode::Be => &["<register>, 16/32/64"],
+            Opcode::Ja => &["<label>"],
+            Opcode::Call => &["<label>", "<syscall>"],
+            Opcode::Exit => &[""],
+            opcode if opcode.is_jump() || matches!(opcode,
+                Opcode::Jeq | Opcode::Jgt | Opcode::Jge | Opcode::Jlt | Opcode::Jle | Opcode::Jset
+                | Opcode::Jne | Opcode::Jsgt | Opcode::Jsge | Opcode::Jslt | Opcode::Jsle) =>
+                &["<register>, <immediate value>, <label>", "<register>, <register>, <label>"],
+            _ => &["<register>, <immediate value>", "<register>, <register>"],
+        }
+    }
+
+    // Whether the first operand is a destination register, stores only
+    // read theirs as the base of the address
+    pub fn writes_dst(&self) -> bool {
+        !self.is_jump() && !matches!(self,
+            Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw
+            | Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw
+            | Opcode::Call | Opcode::Callx | Opcode::Exit)
+    }
+
+    // On v2 the 32 bit add and sub results are sign extended
+    pub fn description_for(&self, version: SbpfVersion) -> &'static str {
+        match self.to_str() {
+            "add32" if version.explicit_sign_extension_of_results() => "dst = (i32)(dst + src), sign extended",
+            "sub32" if version.explicit_sign_extension_of_results() => "dst = (i32)(dst - src), sign extended",
+            _ => self.description(),
+        }
+    }
+
+    // What the instruction does, `src` is the source register or the
+    // immediate depending on the form
+    pub fn description(&self) -> &'static str {
+        match self.to_str() {
+            "lddw" => "dst = imm64, takes two instruction slots",
+            "ldxb" => "dst = *(u8 *)(src + off)",
+            "ldxh" => "dst = *(u16 *)(src + off)",
+            "ldxw" => "dst = *(u32 *)(src + off)",
+            "ldxdw" => "dst = *(u64 *)(src + off)",
+            "stb" => "*(u8 *)(dst + off) = imm",
+            "sth" => "*(u16 *)(dst + off) = imm",
+            "stw" => "*(u32 *)(dst + off) = imm",
+            "stdw" => "*(u64 *)(dst + off) = imm",
+            "stxb" => "*(u8 *)(dst + off) = src",
+            "stxh" => "*(u16 *)(dst + off) = src",
+            "stxw" => "*(u32 *)(dst + off) = src",
+            "stxdw" => "*(u64 *)(dst + off) = src",
+            "add32" => "dst = (u32)(dst + src)",
+            "sub32" => "dst = (u32)(dst - src)",
+            "mul32" => "dst = (u32)(dst * src)",
+            "div32" => "dst = (u32)dst / (u32)src",
+            "or32" => "dst = (u32)(dst | src)",
+            "and32" => "dst = (u32)(dst & src)",
+            "lsh32" => "dst = (u32)dst << src",
+            "rsh32" => "dst = (u32)dst >> src",
+            "neg32" => "dst = (u32)-dst",
+            "mod32" => "dst = (u32)dst % (u32)src",
+            "xor32" => "dst = (u32)(dst ^ src)",
+            "mov32" => "dst = (u32)src",
+            "arsh32" => "dst = (u32)((i32)dst >> src)",
+            "lmul32" => "dst = (u32)(dst * src)",
+            "udiv32" => "dst = (u32)dst / (u32)src",
+            "urem32" => "dst = (u32)dst % (u32)src",
+            "sdiv32" => "dst = (u32)((i32)dst / (i32)src)",
+            "srem32" => "dst = (u32)((i32)dst % (i32)src)",
+            "le" => "dst = htole(dst), truncated to the given width",
+            "be" => "dst = htobe(dst), truncated to the given width",
+            "add64" => "dst += src",
+            "sub64" => "dst -= src",
+            "mul64" => "dst *= src",
+            "div64" => "dst /= src, unsigned",
+            "or64" => "dst |= src",
+            "and64" => "dst &= src",
+            "lsh64" => "dst <<= src",
+            "rsh64" => "dst >>= src, logical",
+            "neg64" => "dst = -dst",
+            "mod64" => "dst %= src, unsigned",
+            "xor64" => "dst ^= src",
+            "mov64" => "dst = src",
+            "arsh64" => "dst >>= src, arithmetic",
+            "hor64" => "dst |= imm << 32",
+            "lmul64" => "dst *= src, lower 64 bits",
+            "uhmul64" => "dst = (dst * src) >> 64, unsigned",
+            "udiv64" => "dst /= src, unsigned",
+            "urem64" => "dst %= src, unsigned",
+            "shmul64" => "dst = (dst * src) >> 64, signed",
+            "sdiv64" => "dst /= src, signed",
+            "srem64" => "dst %= src, signed",
+            "ja" => "goto label",
+            "jeq" => "if dst == src goto label",
+            "jgt" => "if dst > src goto label, unsigned",
+            "jge" => "if dst >= src goto label, unsigned",
+            "jlt" => "if dst < src goto label, unsigned",
+            "jle" => "if dst <= src goto label, unsigned",
+            "jset" => "if dst & src goto label",
+            "jne" => "if dst != src goto label",
+            "jsgt" => "if dst > src goto label, signed",
+            "jsge" => "if dst >= src goto label, signed",
+            "jslt" => "if dst < src goto label, signed",
+            "jsle" => "if dst <= src goto label, signed",
+            "call" => "call a function in .text or a syscall, the result is returned in r0",
+            "callx" => "call the function at the address in the register",
+            "exit" => "return from the function, or end the program with r0 as the result",
+            _ => "",
+        }
+    }
 }
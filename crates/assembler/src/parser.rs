@@ -1,13 +1,21 @@
 use crate::lexer::Op;
+use crate::expression::{Expr, Resolved, parse_expression, starts_expression};
 use crate::opcode::Opcode;
 use crate::lexer::{Token, ImmediateValue};
 use crate::section::{CodeSection, DataSection};
-use crate::astnode::{ASTNode, Directive, GlobalDecl, EquDecl, ExternDecl, RodataDecl, Label, Instruction, ROData};
-use crate::dynsym::{DynamicSymbolMap, RelDynMap, RelocationType};
+use crate::header::ProgramHeader;
+use crate::program::Program;
+use crate::version::SbpfVersion;
+use crate::astnode::{ASTNode, Directive, GlobalDecl, EquDecl, ExternDecl, RodataDecl, TypeDecl, SizeDecl, Label, Instruction, ROData, Align};
+use crate::dynsym::{DynamicSymbolMap, RelDynMap, RelocationType, FunctionSymbol};
 use codespan_reporting::files::SimpleFile;
 use num_traits::FromPrimitive;
 use std::collections::HashMap;
+use std::ops::Range;
 use crate::errors::CompileError;
+use crate::warnings::CompileWarning;
+use crate::syscalls::{SYSCALLS, is_syscall};
+use crate::suggest::suggest;
 use crate::messages::*;
 use crate::bug;
 
@@ -18,16 +26,26 @@ pub struct Parser<> {
     pub m_accum_offset: u64,
 
     // TODO: consolidate all temporary parsing related informaion
-    m_const_map: HashMap<String, ImmediateValue>,
+    // the value of every constant, an expression if it depends on labels
+    m_const_map: HashMap<String, Token>,
+    // constants depending on labels, with the offset `.` stands for
+    m_deferred_consts: Vec<(String, Expr, u64)>,
     m_label_offsets: HashMap<String, u64>,
+    // where each label and constant was defined
+    m_definitions: HashMap<String, Range<usize>>,
 
     // TODO: consolidate all dynamic symbol information to one big map
+    // the first `.globl`, unless `.entry` names another label
     m_entry_label: Option<String>,
+    m_entry_directive: bool,
     m_dynamic_symbols: DynamicSymbolMap,
     m_rel_dyns: RelDynMap,
 
     m_rodata_size: u64,
+    m_text_align: u64,
+    m_rodata_align: u64,
     m_file: Option<SimpleFile<String, String>>,
+    m_version: SbpfVersion,
 }
 
 pub struct ParseResult {
@@ -40,9 +58,15 @@ pub struct ParseResult {
 
     pub relocation_data: RelDynMap,
 
+    pub function_symbols: Vec<FunctionSymbol>,
+
+    pub version: SbpfVersion,
+
     // TODO: this can be removed and dynamic-ness should just be 
     // determined by if there's any dynamic symbol
     pub prog_is_static: bool,
+
+    pub warnings: Vec<CompileWarning>,
 }
 
 // for now, we only return one error per parse for simpler error handling
@@ -51,11 +75,75 @@ pub trait Parse {
         where Self: Sized;
 }
 
+pub trait ParseWithConstMap {
+    fn parse_with_constmap<'a>(tokens: &'a [Token], const_map: &HashMap<String, Token>) -> Result<(Self, &'a [Token]), CompileError>
+        where Self: Sized;
+}
+
 pub trait ParseInstruction {
-    fn parse_instruction<'a>(tokens: &'a [Token], const_map: &HashMap<String, ImmediateValue>) -> Result<(Self, &'a [Token]), CompileError>
+    fn parse_instruction<'a>(tokens: &'a [Token], const_map: &HashMap<String, Token>) -> Result<(Self, &'a [Token]), CompileError>
         where Self: Sized;
 }
 
+// Every directive the parser knows, for suggestions
+pub const DIRECTIVES: &[&str] = &[
+    "globl", "global", "entry", "extern", "rodata", "section", "equ", "type", "size", "align", "balign", "p2align",
+    "byte", "2byte", "short", "4byte", "word", "long", "8byte", "quad",
+    "ascii", "asciz", "string", "zero", "space", "fill",
+];
+
+// Walks the token stream statement by statement. Statements are parsed from
+// `rest()` and hand back whatever they didn't consume to `seek`
+struct TokenCursor<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> TokenCursor<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a [Token] {
+        &self.tokens[self.pos..]
+    }
+
+    // `n` tokens ahead of the current one
+    fn peek(&self, n: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + n)
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.tokens.len());
+    }
+
+    fn seek(&mut self, rest: &'a [Token]) {
+        self.pos = self.tokens.len() - rest.len();
+    }
+
+    // Whether the lexer already reported an error on the rest of the line
+    fn line_has_error(&self) -> bool {
+        self.rest().iter().take_while(|t| !matches!(t, Token::Newline(_))).any(|t| matches!(t, Token::Error(_)))
+    }
+
+    // Moves to the end of the current line, leaving the newline
+    fn skip_line(&mut self) {
+        while let Some(token) = self.peek(0) && !matches!(token, Token::Newline(_)) {
+            self.pos += 1;
+        }
+    }
+
+    // Anything left before the newline is reported as one error
+    fn expect_line_end(&self) -> Result<(), CompileError> {
+        let Some(token) = self.peek(0).filter(|token| !matches!(token, Token::Newline(_))) else {
+            return Ok(());
+        };
+        let line_end = self.rest().iter().take_while(|t| !matches!(t, Token::Newline(_))).last().unwrap_or(token);
+        let custom_label = matches!(token, Token::Opcode(_, _)).then(|| EXPECTS_ONE_INSTRUCTION_PER_LINE.to_string());
+        Err(CompileError::ExpectedEndOfLine { span: token.span().start..line_end.span().end, custom_label })
+    }
+}
+
 impl Parse for GlobalDecl {
     fn parse(tokens: &[Token]) -> Result<(Self, &[Token]), CompileError> {
         let Token::Directive(_, span) = &tokens[0] else { bug!("GlobalDecl not a valid directive") };
@@ -74,33 +162,27 @@ impl Parse for GlobalDecl {
     }
 }
 
-impl Parse for EquDecl {
-    fn parse(tokens: &[Token]) -> Result<(Self, &[Token]), CompileError> {
+impl ParseWithConstMap for EquDecl {
+    fn parse_with_constmap<'a>(tokens: &'a [Token], const_map: &HashMap<String, Token>) -> Result<(Self, &'a [Token]), CompileError> {
         let Token::Directive(_, span) = &tokens[0] else { bug!("EquDecl not a valid directive") };
         if tokens.len() < 3 {
             return Err(CompileError::InvalidEquDecl { span: span.clone(), custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
         }
-        match (
-            &tokens[1],
-            &tokens[2],
-            &tokens[3],
-        ) {
-            (
-                Token::Identifier(name, span),
-                Token::Comma(_),
-                Token::ImmediateValue(_value, _)
-            ) => {
-                Ok((
-                    EquDecl {
-                        name: name.clone(),
-                        value: tokens[3].clone(),
-                        span: span.clone()
-                    },
-                    &tokens[4..]
-                ))
-            }
-            _ => Err(CompileError::InvalidEquDecl { span: span.clone(), custom_label: Some(EXPECTS_IDEN_COM_IMM.to_string()) }),
-        }
+        let invalid = || CompileError::InvalidEquDecl { span: span.clone(), custom_label: Some(EXPECTS_IDEN_COM_IMM.to_string()) };
+        let (Token::Identifier(name, name_span), Token::Comma(_)) = (&tokens[1], &tokens[2]) else {
+            return Err(invalid());
+        };
+        let (Some(value), next) = inline_and_fold_constant(tokens, const_map, 3, false)? else {
+            return Err(invalid());
+        };
+        Ok((
+            EquDecl {
+                name: name.clone(),
+                value,
+                span: name_span.clone()
+            },
+            &tokens[next..]
+        ))
     }
 }
 
@@ -138,46 +220,285 @@ impl Parse for ExternDecl {
     }
 }
 
-impl Parse for ROData {
+impl Parse for TypeDecl {
     fn parse(tokens: &[Token]) -> Result<(Self, &[Token]), CompileError> {
-        let Token::Label(_, span) = &tokens[0] else { bug!("ROData not a valid directive") };
-        if tokens.len() < 3 {
-            return Err(CompileError::InvalidRodataDecl { span: span.clone(), custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
+        let Token::Directive(_, span) = &tokens[0] else { bug!("TypeDecl not a valid directive") };
+        if tokens.len() < 4 {
+            return Err(CompileError::InvalidTypeDecl { span: span.clone(), custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
+        }
+        match (
+            &tokens[1],
+            &tokens[2],
+            &tokens[3],
+        ) {
+            (
+                Token::Identifier(name, span),
+                Token::Comma(_),
+                Token::Identifier(symbol_type, _)
+            ) if symbol_type == "@function" => {
+                Ok((
+                    TypeDecl {
+                        name: name.clone(),
+                        span: span.clone()
+                    },
+                    &tokens[4..]
+                ))
+            }
+            _ => Err(CompileError::InvalidTypeDecl { span: span.clone(), custom_label: Some(EXPECTS_IDEN_COM_FUNCTION.to_string()) }),
         }
+    }
+}
 
-        let mut args = Vec::new();
+impl Parse for SizeDecl {
+    fn parse(tokens: &[Token]) -> Result<(Self, &[Token]), CompileError> {
+        let Token::Directive(_, span) = &tokens[0] else { bug!("SizeDecl not a valid directive") };
+        if tokens.len() < 4 {
+            return Err(CompileError::InvalidSizeDecl { span: span.clone(), custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
+        }
         match (
-            &tokens[0],
             &tokens[1],
             &tokens[2],
+            &tokens[3],
         ) {
             (
-                Token::Label(name, span),
-                Token::Directive(_, _),
-                Token::StringLiteral(_, _)
+                Token::Identifier(name, span),
+                Token::Comma(_),
+                Token::ImmediateValue(_, _)
             ) => {
-                args.push(tokens[1].clone());
-                args.push(tokens[2].clone());
                 Ok((
-                    ROData {
+                    SizeDecl {
                         name: name.clone(),
-                        args,
+                        size: tokens[3].clone(),
                         span: span.clone()
                     },
-                    &tokens[3..]
+                    &tokens[4..]
                 ))
             }
-            _ => Err(CompileError::InvalidRodataDecl { span: span.clone(), custom_label: Some(EXPECTS_LABEL_DIR_STR.to_string()) }),
+            _ => Err(CompileError::InvalidSizeDecl { span: span.clone(), custom_label: Some(EXPECTS_IDEN_COM_IMM.to_string()) }),
         }
     }
 }
 
+impl ParseWithConstMap for ROData {
+    fn parse_with_constmap<'a>(tokens: &'a [Token], const_map: &HashMap<String, Token>) -> Result<(Self, &'a [Token]), CompileError> {
+        // the label is optional, data can follow on its own line
+        let (name, tokens) = match &tokens[0] {
+            Token::Label(name, _) => (Some(name.clone()), &tokens[1..]),
+            _ => (None, tokens),
+        };
+        let Some(Token::Directive(directive, span)) = tokens.first() else {
+            bug!("ROData not a valid directive")
+        };
+        if !ROData::is_data_directive(directive) {
+            return Err(CompileError::InvalidDirective { directive: directive.clone(), span: span.clone(), custom_label: None });
+        }
+        let invalid = |label: String| CompileError::InvalidRodataDecl { span: span.clone(), custom_label: Some(label) };
+
+        let mut args = vec![tokens[0].clone()];
+        let mut idx = 1;
+        while idx < tokens.len() && !matches!(tokens[idx], Token::Newline(_)) {
+            if args.len() > 1 {
+                match &tokens[idx] {
+                    Token::Comma(_) if idx + 1 < tokens.len() => idx += 1,
+                    _ => return Err(invalid(EXPECTS_COMMA_SEPARATED_VALUES.to_string())),
+                }
+            }
+            if let Token::StringLiteral(_, _) = &tokens[idx] {
+                args.push(tokens[idx].clone());
+                idx += 1;
+                continue;
+            }
+            match inline_and_fold_constant(tokens, const_map, idx, true)? {
+                (Some(value), next) => {
+                    args.push(value);
+                    idx = next;
+                }
+                // label reference, resolved to its address once every label is
+                // known, unless it names a constant defined further down
+                (None, next) if let Token::Identifier(name, span) = &tokens[idx] => {
+                    if ROData::value_size(directive) == Some(8) {
+                        args.push(tokens[idx].clone());
+                    } else {
+                        args.push(Token::Expression(Expr::Symbol(name.clone(), span.clone()), span.clone()));
+                    }
+                    idx = next;
+                }
+                _ => return Err(invalid(EXPECTS_COMMA_SEPARATED_VALUES.to_string())),
+            }
+        }
+
+        let values = &args[1..];
+        let int = |token: &Token| match token {
+            Token::ImmediateValue(value, _) => Some(value.to_i64()),
+            _ => None,
+        };
+        match directive.as_str() {
+            "ascii" | "asciz" | "string" => {
+                if values.is_empty() || !values.iter().all(|v| matches!(v, Token::StringLiteral(_, _))) {
+                    return Err(invalid(EXPECTS_STR_LIST.to_string()));
+                }
+            }
+            "zero" | "space" | "fill" => {
+                let max_len = match directive.as_str() { "zero" => 1, "space" => 2, _ => 3 };
+                let ints: Vec<_> = values.iter().map(int).collect();
+                let label = match directive.as_str() {
+                    "zero" => EXPECTS_COUNT,
+                    "space" => EXPECTS_COUNT_FILL,
+                    _ => EXPECTS_REPEAT_SIZE_VALUE,
+                };
+                if values.is_empty() || values.len() > max_len || ints.iter().any(|v| v.is_none())
+                    || ints[0].unwrap() < 0 {
+                    return Err(invalid(label.to_string()));
+                }
+                let value_count = values.len();
+                // .space fills with 0 and .fill repeats a single 0 byte unless told otherwise
+                if directive == "space" && value_count == 1 {
+                    args.push(Token::ImmediateValue(ImmediateValue::Int(0), span.clone()));
+                }
+                if directive == "fill" {
+                    if value_count < 2 {
+                        args.push(Token::ImmediateValue(ImmediateValue::Int(1), span.clone()));
+                    }
+                    if value_count < 3 {
+                        args.push(Token::ImmediateValue(ImmediateValue::Int(0), span.clone()));
+                    }
+                    if !(1..=8).contains(&int(&args[2]).unwrap()) {
+                        return Err(invalid(EXPECTS_FILL_SIZE.to_string()));
+                    }
+                }
+            }
+            _ => {
+                let size = ROData::value_size(directive).unwrap();
+                if values.is_empty() {
+                    return Err(invalid(EXPECTS_IMM_LIST.to_string()));
+                }
+                for value in values {
+                    match value {
+                        // only 8 byte values are wide enough to hold an address
+                        Token::Identifier(_, _) if size == 8 => {}
+                        Token::ImmediateValue(imm, value_span) => check_data_value(directive, imm, value_span)?,
+                        // checked once it's evaluated
+                        Token::Expression(_, _) => {}
+                        _ => return Err(invalid(if size == 8 { EXPECTS_IMM_OR_IDEN_LIST } else { EXPECTS_IMM_LIST }.to_string())),
+                    }
+                }
+            }
+        }
+
+        Ok((
+            ROData {
+                name,
+                args,
+                span: span.clone()
+            },
+            &tokens[idx..]
+        ))
+    }
+}
+
+// The operands limited by their encoding, by index, with the accepted range.
+// 32 bit immediates are sign extended for 64 bit operations, so only 32 bit
+// operations take them unsigned
+fn operand_ranges(opcode: Opcode) -> &'static [(usize, i128, i128, &'static str)] {
+    const OFFSET16: i128 = i16::MIN as i128;
+    const IMM32: i128 = i32::MIN as i128;
+    match opcode {
+        Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw =>
+            &[(2, OFFSET16, i16::MAX as i128, EXPECTS_OFFSET16)],
+        Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw
+        | Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw =>
+            &[(1, OFFSET16, i16::MAX as i128, EXPECTS_OFFSET16)],
+        Opcode::Lsh32Imm | Opcode::Rsh32Imm | Opcode::Arsh32Imm => &[(1, 0, 31, EXPECTS_SHIFT_AMOUNT_32)],
+        Opcode::Lsh64Imm | Opcode::Rsh64Imm | Opcode::Arsh64Imm => &[(1, 0, 63, EXPECTS_SHIFT_AMOUNT)],
+        Opcode::Add32Imm | Opcode::Sub32Imm | Opcode::Mul32Imm | Opcode::Div32Imm
+        | Opcode::Or32Imm | Opcode::And32Imm | Opcode::Mod32Imm | Opcode::Xor32Imm
+        | Opcode::Mov32Imm | Opcode::Lmul32Imm | Opcode::Udiv32Imm | Opcode::Urem32Imm
+        | Opcode::Sdiv32Imm | Opcode::Srem32Imm | Opcode::Hor64Imm =>
+            &[(1, IMM32, u32::MAX as i128, EXPECTS_IMM32)],
+        Opcode::Add64Imm | Opcode::Sub64Imm | Opcode::Mul64Imm | Opcode::Div64Imm
+        | Opcode::Or64Imm | Opcode::And64Imm | Opcode::Mod64Imm | Opcode::Xor64Imm
+        | Opcode::Mov64Imm | Opcode::Lmul64Imm | Opcode::Uhmul64Imm | Opcode::Udiv64Imm
+        | Opcode::Urem64Imm | Opcode::Shmul64Imm | Opcode::Sdiv64Imm | Opcode::Srem64Imm
+        | Opcode::JeqImm | Opcode::JgtImm | Opcode::JgeImm | Opcode::JltImm
+        | Opcode::JleImm | Opcode::JsetImm | Opcode::JneImm | Opcode::JsgtImm
+        | Opcode::JsgeImm | Opcode::JsltImm | Opcode::JsleImm =>
+            &[(1, IMM32, i32::MAX as i128, EXPECTS_SIGNED_IMM32)],
+        _ => &[],
+    }
+}
+
+// Values may be given signed or unsigned, as long as they fit the data size
+fn check_data_value(directive: &str, value: &ImmediateValue, span: &Range<usize>) -> Result<(), CompileError> {
+    let bits = ROData::value_size(directive).unwrap() * 8;
+    let value = value.to_i128();
+    if value < -(1 << (bits - 1)) || value >= 1 << bits {
+        return Err(CompileError::InvalidRodataDecl {
+            span: span.clone(),
+            custom_label: Some(format!("value does not fit in .{}", directive)) });
+    }
+    Ok(())
+}
+
+impl ParseWithConstMap for Align {
+    fn parse_with_constmap<'a>(tokens: &'a [Token], const_map: &HashMap<String, Token>) -> Result<(Self, &'a [Token]), CompileError> {
+        let Token::Directive(directive, span) = &tokens[0] else { bug!("Align not a valid directive") };
+        let expects = if directive == "p2align" { EXPECTS_P2ALIGN_FILL } else { EXPECTS_ALIGN_FILL };
+        let invalid = |label: &str| CompileError::InvalidAlignDecl { span: span.clone(), custom_label: Some(label.to_string()) };
+
+        let mut values = Vec::new();
+        let mut idx = 1;
+        while idx < tokens.len() && !matches!(tokens[idx], Token::Newline(_)) {
+            if !values.is_empty() {
+                match &tokens[idx] {
+                    Token::Comma(_) if idx + 1 < tokens.len() => idx += 1,
+                    _ => return Err(invalid(expects)),
+                }
+            }
+            match inline_and_fold_constant(tokens, const_map, idx, false)? {
+                (Some(Token::ImmediateValue(value, _)), next) => {
+                    values.push(value.to_i128());
+                    idx = next;
+                }
+                // the padding has to be known while laying out the program
+                (Some(_), _) => return Err(invalid(EXPECTS_CONSTANT)),
+                _ => return Err(invalid(expects)),
+            }
+        }
+
+        let (alignment, fill) = match values[..] {
+            [alignment] => (alignment, None),
+            [alignment, fill] if (0..=0xff).contains(&fill) => (alignment, Some(fill as u8)),
+            _ => return Err(invalid(expects)),
+        };
+        // .align counts bytes, same as .balign
+        let alignment = match directive.as_str() {
+            "p2align" if (0..=12).contains(&alignment) => 1 << alignment,
+            "p2align" => return Err(invalid(EXPECTS_POW2_ALIGN)),
+            _ => alignment,
+        };
+        if !(1..=4096).contains(&alignment) || alignment & (alignment - 1) != 0 {
+            return Err(invalid(EXPECTS_POW2_ALIGN));
+        }
+
+        Ok((
+            Align {
+                directive: directive.clone(),
+                alignment: alignment as u64,
+                fill,
+                padding: 0,
+                span: span.clone(),
+            },
+            &tokens[idx..]
+        ))
+    }
+}
+
 impl ParseInstruction for Instruction {
-    fn parse_instruction<'a>(tokens: &'a [Token], const_map: &HashMap<String, ImmediateValue>) -> Result<(Self, &'a [Token]), CompileError> {
+    fn parse_instruction<'a>(tokens: &'a [Token], const_map: &HashMap<String, Token>) -> Result<(Self, &'a [Token]), CompileError> {
         let next_token_num;
         match &tokens[0] {
             Token::Opcode(opcode, span) => {
-                let mut opcode = opcode.clone();
+                let mut opcode = *opcode;
                 let mut operands = Vec::new();
                 match opcode {
                     Opcode::Lddw => {
@@ -188,7 +509,7 @@ impl ParseInstruction for Instruction {
                                     , span: span.clone()            //
                                     , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3);
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3, true)?;
                         if let Some(value) = value {
                             match (
                                 &tokens[1],
@@ -201,7 +522,7 @@ impl ParseInstruction for Instruction {
                                     // Third operand is folded to an immediate value
                                 ) => {
                                     operands.push(tokens[1].clone());
-                                    operands.push(Token::ImmediateValue(value, span.clone()));
+                                    operands.push(value);
                                 }
                                 _ => {
                                     return Err(
@@ -245,29 +566,29 @@ impl ParseInstruction for Instruction {
                                     , span: span.clone()            //
                                     , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 6);
-                        if let Some(value) = value {
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 6, false)?;
+                        if let Some(value) = value.map(|value| signed_offset(&tokens[5], value)) {
                             match (
                                 &tokens[1],
                                 &tokens[2],
                                 &tokens[3],
                                 &tokens[4],
                                 &tokens[5],
-                                // Sixth operand is folded to an immediate value
-                                &tokens[advance_token_num],
+                                // Sixth operand onwards is folded to an immediate value
+                                tokens.get(advance_token_num),
                             ) {
                                 (
                                     Token::Register(_, _),
                                     Token::Comma(_),
                                     Token::LeftBracket(_),
                                     Token::Register(_, _),
-                                    Token::BinaryOp(_, _),
-                                    // Sixth operand is folded to an immediate value 
-                                    Token::RightBracket(_)
+                                    Token::BinaryOp(Op::Add | Op::Sub, _),
+                                    // Sixth operand onwards is folded to an immediate value
+                                    Some(Token::RightBracket(_))
                                 ) => {
                                     operands.push(tokens[1].clone());
                                     operands.push(tokens[4].clone());
-                                    operands.push(Token::ImmediateValue(value, span.clone()));                                    
+                                    operands.push(value);                                    
                                 }
                                 _ => {
                                     return Err(
@@ -295,28 +616,28 @@ impl ParseInstruction for Instruction {
                                     , span: span.clone()            //
                                     , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 4);
-                        if let Some(value) = value {
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 4, false)?;
+                        if let Some(value) = value.map(|value| signed_offset(&tokens[3], value)) {
                             match (
                                 &tokens[1],
                                 &tokens[2],
                                 &tokens[3],
-                                // Fourth operand is folded to an immediate value
-                                &tokens[advance_token_num],
-                                &tokens[advance_token_num + 1],
-                                &tokens[advance_token_num + 2],
+                                // Fourth operand onwards is folded to an immediate value
+                                tokens.get(advance_token_num),
+                                tokens.get(advance_token_num + 1),
+                                tokens.get(advance_token_num + 2),
                             ) {
                                 (
                                     Token::LeftBracket(_),
                                     Token::Register(_, _),
-                                    Token::BinaryOp(_, _),
-                                    // Fourth operand is folded to an immediate value
-                                    Token::RightBracket(_),
-                                    Token::Comma(_),
-                                    Token::Register(_, _)
+                                    Token::BinaryOp(Op::Add | Op::Sub, _),
+                                    // Fourth operand onwards is folded to an immediate value
+                                    Some(Token::RightBracket(_)),
+                                    Some(Token::Comma(_)),
+                                    Some(Token::Register(_, _))
                                 ) => {
                                     operands.push(tokens[2].clone());
-                                    operands.push(Token::ImmediateValue(value, span.clone()));
+                                    operands.push(value);
                                     operands.push(tokens[advance_token_num + 2].clone());
                                 }
                                 _ => {
@@ -341,13 +662,13 @@ impl ParseInstruction for Instruction {
                     | Opcode::Lsh32 | Opcode::Rsh32 | Opcode::Mod32 
                     | Opcode::Xor32 | Opcode::Mov32 | Opcode::Arsh32 
                     | Opcode::Lmul32 | Opcode::Udiv32 | Opcode::Urem32 
-                    | Opcode::Sdiv32 | Opcode::Srem32 | Opcode::Neg32
+                    | Opcode::Sdiv32 | Opcode::Srem32
                     | Opcode::Add64 | Opcode::Sub64 | Opcode::Mul64 
                     | Opcode::Div64 | Opcode::Or64 | Opcode::And64 
                     | Opcode::Lsh64 | Opcode::Rsh64 | Opcode::Mod64 
                     | Opcode::Xor64 | Opcode::Mov64 | Opcode::Arsh64 
                     | Opcode::Lmul64 | Opcode::Uhmul64 | Opcode::Udiv64 
-                    | Opcode::Urem64 | Opcode::Sdiv64 | Opcode::Srem64 => {
+                    | Opcode::Urem64 | Opcode::Shmul64 | Opcode::Sdiv64 | Opcode::Srem64 => {
                         if tokens.len() < 4 {
                             return Err(
                                 CompileError::InvalidInstruction {  //
@@ -355,7 +676,7 @@ impl ParseInstruction for Instruction {
                                     , span: span.clone()            //
                                     , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3);
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3, false)?;
                         if let Some(value) = value {
                             match (
                                 &tokens[1],
@@ -369,7 +690,7 @@ impl ParseInstruction for Instruction {
                                 ) => {
                                     opcode = FromPrimitive::from_u8((opcode as u8) + 1).expect("Invalid opcode conversion"); 
                                     operands.push(tokens[1].clone());
-                                    operands.push(Token::ImmediateValue(value, span.clone()));
+                                    operands.push(value);
                                 }
                                 _ => {
                                     return Err(
@@ -417,27 +738,35 @@ impl ParseInstruction for Instruction {
                                     , span: span.clone()            //
                                     , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3);
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3, false)?;
                         if let Some(value) = value {
                             match (
                                 &tokens[1],
                                 &tokens[2],
                                 // Third operand is folded to an immediate value
-                                &tokens[advance_token_num],
-                                &tokens[advance_token_num + 1],
+                                tokens.get(advance_token_num),
+                                tokens.get(advance_token_num + 1),
                             ) {
                                 (
                                     Token::Register(_, _),
                                     Token::Comma(_),
                                     // Third operand is folded to an immediate value
-                                    Token::Comma(_),
-                                    Token::Identifier(_, _)
+                                    Some(Token::Comma(_)),
+                                    Some(Token::Identifier(_, _))
                                 ) => {
                                     opcode = FromPrimitive::from_u8((opcode as u8) + 1).expect("Invalid opcode conversion"); 
                                     operands.push(tokens[1].clone());
-                                    operands.push(Token::ImmediateValue(value, span.clone()));
+                                    operands.push(value);
                                     operands.push(tokens[advance_token_num + 1].clone());
                                 }
+                                // the immediate ends the file
+                                (_, _, None, _) | (_, _, _, None) => {
+                                    return Err(
+                                        CompileError::InvalidInstruction {  //
+                                            instruction: opcode.to_string() //
+                                            , span: span.clone()            //
+                                            , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
+                                }
                                 _ => {
                                     return Err(
                                         CompileError::InvalidInstruction {  //
@@ -487,9 +816,9 @@ impl ParseInstruction for Instruction {
                                     , span: span.clone()            //
                                     , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 1);
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 1, true)?;
                         if let Some(value) = value {
-                            operands.push(Token::ImmediateValue(value, span.clone()));
+                            operands.push(value);
                             next_token_num = advance_token_num;
                         } else {
                             match &tokens[1] {
@@ -529,11 +858,61 @@ impl ParseInstruction for Instruction {
                         }
                         next_token_num = 2;
                     }
+                    Opcode::Hor64Imm | Opcode::Le | Opcode::Be => {
+                        if tokens.len() < 4 {
+                            return Err(
+                                CompileError::InvalidInstruction {  //
+                                    instruction: opcode.to_string() //
+                                    , span: span.clone()            //
+                                    , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
+                        }
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3, false)?;
+                        match (value, &tokens[1], &tokens[2]) {
+                            (Some(value), Token::Register(_, _), Token::Comma(_)) => {
+                                operands.push(tokens[1].clone());
+                                operands.push(value);
+                            }
+                            _ => {
+                                return Err(
+                                    CompileError::InvalidInstruction {  //
+                                        instruction: opcode.to_string() //
+                                        , span: span.clone()            //
+                                        , custom_label: Some(EXPECTS_REG_COM_IMM.to_string()) });
+                            }
+                        }
+                        next_token_num = advance_token_num;
+                    }
+                    Opcode::Neg32 | Opcode::Neg64 | Opcode::Callx => {
+                        if tokens.len() < 2 {
+                            return Err(
+                                CompileError::InvalidInstruction {  //
+                                    instruction: opcode.to_string() //
+                                    , span: span.clone()            //
+                                    , custom_label: Some(EXPECTS_MORE_OPERAND.to_string()) });
+                        }
+                        match &tokens[1] {
+                            Token::Register(_, _) => {
+                                operands.push(tokens[1].clone());
+                            }
+                            _ => {
+                                return Err(
+                                    CompileError::InvalidInstruction {  //
+                                        instruction: opcode.to_string() //
+                                        , span: span.clone()            //
+                                        , custom_label: Some(EXPECTS_REG.to_string()) });
+                            }
+                        }
+                        next_token_num = 2;
+                    }
                     Opcode::Exit => {
                         next_token_num = 1;
                     }
                     _ => {
-                        bug!("invalid opcode: {}", opcode.to_str());
+                        return Err(
+                            CompileError::InvalidInstruction {  //
+                                instruction: opcode.to_string() //
+                                , span: span.clone()            //
+                                , custom_label: None });
                     }
                 }
                 Ok((
@@ -553,69 +932,395 @@ impl ParseInstruction for Instruction {
     }
 }
 
-fn inline_and_fold_constant_helper(tokens: &[Token]                             //
-                                , const_map: &HashMap<String, ImmediateValue>   //
-                                , value: ImmediateValue                         //
-                                , idx: usize) -> (Option<ImmediateValue>, usize) {
-    if tokens.len() < idx + 3 {
-        return (Some(value), idx + 1);
-    }
-
-    match (
-        &tokens[idx + 1],
-        &tokens[idx + 2],
-    ) {
-        (
-            Token::BinaryOp(op, _),
-            Token::ImmediateValue(value2, _)
-        ) => {
-            let result = match op {
-                Op::Add => {
-                    value + value2.clone()
-                }
-                Op::Sub => {
-                    value - value2.clone()
-                }
-            };
-            inline_and_fold_constant_helper(tokens, const_map, result, idx + 2)
-        }
-        _ => (Some(value), idx + 1),
+// Memory offsets are folded on their own after the `+` or `-` and negated
+// for `-`, as a unary minus it would only apply to the first term
+fn signed_offset(op: &Token, value: Token) -> Token {
+    let Token::BinaryOp(op, op_span) = op else { return value };
+    let span = op_span.start..value.span().end;
+    match (op, value) {
+        (Op::Sub, Token::ImmediateValue(value, _)) => Token::ImmediateValue(ImmediateValue::from_i128(-value.to_i128(), false), span),
+        (Op::Sub, Token::Expression(expr, _)) => Token::Expression(Expr::Neg(Box::new(expr), op_span.clone()), span),
+        (_, Token::ImmediateValue(value, _)) => Token::ImmediateValue(value, span),
+        (_, Token::Expression(expr, _)) => Token::Expression(expr, span),
+        (_, value) => value,
     }
 }
 
+// Folds the constant expression starting at `idx`. None when the operand isn't
+// an expression, or is a bare identifier that isn't a constant where a label is
+// allowed. Expressions on anything but the constants defined so far, like
+// `. - message`, are kept as is and evaluated once every label is known
 fn inline_and_fold_constant(tokens: &[Token]                            //
-                        , const_map: &HashMap<String, ImmediateValue>   //
-                        , idx: usize) -> (Option<ImmediateValue>, usize) {
-    let value = match &tokens[idx] {
-        Token::ImmediateValue(value, _) => value.clone(),
-        Token::Identifier(name, _) => {
-            if let Some(val) = const_map.get(name) {
-                val.clone()
-            } else {
-                return (None, idx + 1);
-            }
-        },
-        _ => return (None, idx + 1),
+                        , const_map: &HashMap<String, Token>            //
+                        , idx: usize                                    //
+                        , label_allowed: bool) -> Result<(Option<Token>, usize), CompileError> {
+    match tokens.get(idx) {
+        Some(token) if starts_expression(token) => {}
+        _ => return Ok((None, idx + 1)),
+    }
+    let (expr, next) = parse_expression(tokens, idx)?;
+    if label_allowed && let Expr::Symbol(name, _) = &expr && name != "." && !const_map.contains_key(name) {
+        return Ok((None, idx + 1));
+    }
+    let span = tokens[idx].span().start..tokens[next - 1].span().end;
+    let constant = |name: &str| match const_map.get(name) {
+        Some(Token::ImmediateValue(value, _)) => Some(Resolved::Constant(value.clone())),
+        _ => None,
     };
-    inline_and_fold_constant_helper(tokens, const_map, value, idx)
+    match expr.evaluate(&constant) {
+        Ok(Resolved::Constant(value)) => Ok((Some(Token::ImmediateValue(value, span)), next)),
+        Ok(Resolved::Address(_)) => bug!("constants never resolve to an address"),
+        Err(CompileError::UndefinedLabel { .. }) => Ok((Some(Token::Expression(expr, span)), next)),
+        Err(e) => Err(e),
+    }
 }
 
 impl Parser {
 
-    pub fn new(tokens: Vec<Token>, file: &SimpleFile<String, String>) -> Self {
+    pub fn new(mut tokens: Vec<Token>, file: &SimpleFile<String, String>) -> Self {
+        tokens.retain(|token| !matches!(token, Token::Comment(_, _)));
         Self { tokens
             , m_prog_is_static: true
             , m_accum_offset: 0
             , m_entry_label: None
+            , m_entry_directive: false
             , m_const_map: HashMap::new()
+            , m_deferred_consts: Vec::new()
             , m_label_offsets: HashMap::new()
+            , m_definitions: HashMap::new()
             , m_rodata_size: 0
+            , m_text_align: 1
+            , m_rodata_align: 1
             , m_dynamic_symbols: DynamicSymbolMap::new()
             , m_rel_dyns: RelDynMap::new()
             , m_file: Some(file.clone())
+            , m_version: SbpfVersion::default()
+        }
+    }
+
+    pub fn with_version(mut self, version: SbpfVersion) -> Self {
+        self.m_version = version;
+        self
+    }
+
+    // The symbol tables as far as parsing got, also when it failed
+    pub fn label_offset(&self, name: &str) -> Option<u64> {
+        self.m_label_offsets.get(name).copied()
+    }
+
+    pub fn constant(&self, name: &str) -> Option<&Token> {
+        self.m_const_map.get(name)
+    }
+
+    // Rodata labels are laid out after the last instruction, so anything
+    // below the final code size belongs to .text
+    fn is_text_label(&self, name: &str) -> bool {
+        self.m_label_offsets.get(name).is_some_and(|offset| *offset < self.m_accum_offset)
+    }
+
+    fn source_text(&self, span: &Range<usize>) -> String {
+        self.m_file.as_ref().map_or(String::new(), |file| file.source()[span.clone()].to_string())
+    }
+
+    // Labels and constants share one namespace, a redefinition is reported
+    // against the first one, which stays in effect
+    fn define(&mut self, name: &str, span: &Range<usize>, is_label: bool, errors: &mut Vec<CompileError>) -> bool {
+        let Some(original) = self.m_definitions.get(name) else {
+            self.m_definitions.insert(name.to_string(), span.clone());
+            return true;
+        };
+        let original = original.clone();
+        let was_label = self.m_label_offsets.contains_key(name);
+        let custom_label = match (is_label, was_label) {
+            (true, false) => Some(ALREADY_DEFINED_AS_CONSTANT.to_string()),
+            (false, true) => Some(ALREADY_DEFINED_AS_LABEL.to_string()),
+            _ => None,
+        };
+        errors.push(if is_label {
+            CompileError::DuplicateLabel { label: name.to_string(), span: span.clone(), original, custom_label }
+        } else {
+            CompileError::DuplicateConstant { name: name.to_string(), span: span.clone(), original, custom_label }
+        });
+        false
+    }
+
+    // Data is laid out right after the previous entry
+    fn parse_rodata<'a>(&mut self, tokens: &'a [Token], rodata_nodes: &mut Vec<ASTNode>, errors: &mut Vec<CompileError>) -> &'a [Token] {
+        match ROData::parse_with_constmap(tokens, &self.m_const_map) {
+            Ok((rodata, rest)) => {
+                let offset = self.m_accum_offset + self.m_rodata_size;
+                if let Some(name) = &rodata.name
+                    && self.define(name, tokens[0].span(), true, errors) {
+                    self.m_label_offsets.insert(name.clone(), offset);
+                }
+                self.m_rodata_size += rodata.get_size();
+                rodata_nodes.push(ASTNode::ROData { rodata, offset });
+                rest
+            }
+            Err(e) => {
+                errors.push(e);
+                // skip the rest of the declaration
+                let line_end = tokens.iter().position(|t| matches!(t, Token::Newline(_))).unwrap_or(tokens.len());
+                &tokens[line_end..]
+            }
+        }
+    }
+
+    // Legacy programs are loaded at their file offsets, while v3 maps .text
+    // and .rodata at fixed addresses
+    fn label_address(&self, offset: u64) -> u64 {
+        if !self.m_version.enable_stricter_elf_headers() {
+            let ph_count = if self.m_prog_is_static { 1 } else { 3 };
+            let (text_offset, rodata_offset) = Program::legacy_section_offsets(
+                ph_count, self.m_accum_offset, self.m_text_align, self.m_rodata_align);
+            if offset < self.m_accum_offset {
+                text_offset + offset
+            } else {
+                rodata_offset + (offset - self.m_accum_offset)
+            }
+        } else if offset < self.m_accum_offset {
+            ProgramHeader::MM_BYTECODE_START + offset
+        } else {
+            ProgramHeader::MM_RODATA_START + (offset - self.m_accum_offset)
         }
     }
 
+    // Symbols of expressions evaluated after layout, `.` is the address of
+    // the statement at `location`
+    fn resolve_symbol(&self, name: &str, location: u64, consts: &HashMap<String, Resolved>) -> Option<Resolved> {
+        if name == "." {
+            return Some(Resolved::Address(self.label_address(location)));
+        }
+        match self.m_const_map.get(name) {
+            Some(Token::ImmediateValue(value, _)) => return Some(Resolved::Constant(value.clone())),
+            Some(_) => return consts.get(name).cloned(),
+            None => {}
+        }
+        self.m_label_offsets.get(name).map(|offset| Resolved::Address(self.label_address(*offset)))
+    }
+
+    // Constants defined through labels may refer to each other in any order,
+    // they're evaluated until no more can be, what's left is undefined or circular
+    fn resolve_deferred_consts(&self, errors: &mut Vec<CompileError>) -> HashMap<String, Resolved> {
+        let mut consts = HashMap::new();
+        let mut pending: Vec<_> = self.m_deferred_consts.iter().collect();
+        loop {
+            let count = pending.len();
+            pending.retain(|(name, expr, location)| {
+                match expr.evaluate(&|symbol| self.resolve_symbol(symbol, *location, &consts)) {
+                    Ok(value) => {
+                        consts.insert(name.clone(), value);
+                        false
+                    }
+                    Err(_) => true,
+                }
+            });
+            if pending.len() == count {
+                break;
+            }
+        }
+        for (_, expr, location) in &pending {
+            match expr.evaluate(&|symbol| self.resolve_symbol(symbol, *location, &consts)) {
+                Err(CompileError::UndefinedLabel { label, span, .. }) if pending.iter().any(|(name, _, _)| *name == label) =>
+                    errors.push(CompileError::UndefinedLabel { label, span, suggestion: None, custom_label: Some("Circular constant definition".to_string()) }),
+                Err(e) => errors.push(e),
+                Ok(_) => {}
+            }
+        }
+        consts
+    }
+
+    // Evaluates an operand or data value that depends on labels. Addresses
+    // only fit where a relocation was recorded for them
+    fn resolve_expression(&self, token: &mut Token, location: u64, consts: &HashMap<String, Resolved>, address_allowed: bool) -> Result<(), CompileError> {
+        if let Token::Expression(expr, span) = token {
+            match expr.evaluate(&|symbol| self.resolve_symbol(symbol, location, consts))? {
+                Resolved::Constant(value) => *token = Token::ImmediateValue(value, span.clone()),
+                Resolved::Address(address) if address_allowed => *token = Token::ImmediateValue(ImmediateValue::Addr(address as i64), span.clone()),
+                Resolved::Address(_) => return Err(CompileError::InvalidAddressExpression {
+                    span: span.clone(), custom_label: Some(EXPECTS_CONSTANT_NOT_ADDRESS.to_string()) }),
+            }
+        }
+        Ok(())
+    }
+
+    // Immediates and offsets have to fit their encoding, they'd be silently
+    // truncated otherwise
+    fn check_operand_ranges(&self, nodes: &[ASTNode], errors: &mut Vec<CompileError>) {
+        for node in nodes {
+            if let ASTNode::Instruction { instruction: Instruction { opcode, operands, .. }, .. } = node {
+                for (idx, min, max, label) in operand_ranges(*opcode) {
+                    if let Some(Token::ImmediateValue(value, span)) = operands.get(*idx)
+                        && !(*min..=*max).contains(&value.to_i128()) {
+                        errors.push(CompileError::ImmediateOutOfRange {
+                            value: value.to_i128(), span: span.clone(), custom_label: Some(label.to_string()) });
+                    }
+                }
+                // v2 encodes `sub64 dst, imm` as an add of the negated immediate
+                if *opcode == Opcode::Sub64Imm && self.m_version.swap_sub_reg_imm_operands()
+                    && let Some(Token::ImmediateValue(value, span)) = operands.get(1)
+                    && value.to_i128() == i32::MIN as i128 {
+                    errors.push(CompileError::ImmediateOutOfRange {
+                        value: value.to_i128(), span: span.clone(), custom_label: Some(EXPECTS_NEGATABLE_IMM32.to_string()) });
+                }
+                // byte swaps take the width in bits
+                if matches!(opcode, Opcode::Le | Opcode::Be)
+                    && let Some(Token::ImmediateValue(value, span)) = operands.get(1)
+                    && !matches!(value.to_i128(), 16 | 32 | 64) {
+                    errors.push(CompileError::ImmediateOutOfRange {
+                        value: value.to_i128(), span: span.clone(), custom_label: Some(EXPECTS_SWAP_WIDTH.to_string()) });
+                }
+            }
+        }
+    }
+
+    // Registers go up to r10, the frame pointer, which is read-only except
+    // for `add64 r10, imm` with dynamic stack frames
+    fn check_registers(&self, nodes: &[ASTNode], errors: &mut Vec<CompileError>) {
+        for node in nodes {
+            if let ASTNode::Instruction { instruction: Instruction { opcode, operands, .. }, .. } = node {
+                for operand in operands {
+                    if let Token::Register(register, span) = operand && *register > 10 {
+                        errors.push(CompileError::InvalidRegister {
+                            register: format!("r{}", register), span: span.clone(), custom_label: Some(EXPECTS_REG_R0_R10.to_string()) });
+                    }
+                }
+                if let Some(Token::Register(10, span)) = operands.first() && opcode.writes_dst() {
+                    if self.m_version.dynamic_stack_frames() && *opcode == Opcode::Add64Imm {
+                        continue;
+                    }
+                    let custom_label = self.m_version.dynamic_stack_frames().then(|| EXPECTS_ADD64_TO_ADJUST_R10.to_string());
+                    errors.push(CompileError::ReadOnlyRegister { span: span.clone(), custom_label });
+                }
+            }
+        }
+    }
+
+    // Undefined names are matched against the labels and constants, call
+    // targets against the syscalls and externs too
+    fn add_suggestions(&self, errors: &mut [CompileError], externs: &[String]) {
+        for error in errors {
+            if let CompileError::UndefinedLabel { label, suggestion, custom_label, .. } = error {
+                let names = self.m_definitions.keys().map(String::as_str);
+                *suggestion = if custom_label.as_deref() == Some(EXPECTS_FUNCTION_OR_SYSCALL) {
+                    suggest(label, names.chain(SYSCALLS.iter().copied()).chain(externs.iter().map(String::as_str)))
+                } else {
+                    suggest(label, names)
+                }.map(str::to_string);
+            }
+        }
+    }
+
+    // Lints over the finished program. A name counts as used wherever it
+    // appears other than its own definition or an .extern declaration
+    fn collect_warnings(&self, nodes: &[ASTNode]) -> Vec<CompileWarning> {
+        let mut warnings = Vec::new();
+        let externs: Vec<&Token> = nodes.iter()
+            .filter_map(|node| match node {
+                ASTNode::ExternDecl { extern_decl } => Some(extern_decl.args.iter()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let used = |name: &str, definition: &Range<usize>| self.tokens.iter().any(|token| match token {
+            Token::Identifier(identifier, span) => identifier == name && span != definition
+                && !externs.iter().any(|arg| arg.span() == span),
+            _ => false,
+        });
+
+        for (name, span) in &self.m_definitions {
+            if used(name, span) {
+                continue;
+            }
+            let (name, span) = (name.clone(), span.clone());
+            warnings.push(if self.m_label_offsets.contains_key(&name) {
+                CompileWarning::UnusedLabel { label: name, span, custom_label: None }
+            } else {
+                CompileWarning::UnusedConstant { name, span, custom_label: None }
+            });
+        }
+        for arg in &externs {
+            if let Token::Identifier(name, span) = arg && !used(name, span) {
+                warnings.push(CompileWarning::UnusedExtern { name: name.clone(), span: span.clone(), custom_label: None });
+            }
+        }
+
+        // code after exit or ja is only reached through a label, one warning
+        // per unreachable run
+        let mut reachable = true;
+        let mut reported = false;
+        let mut last = None;
+        for node in nodes {
+            match node {
+                ASTNode::Label { .. } => reachable = true,
+                ASTNode::Instruction { instruction, .. } => {
+                    if !reachable && !reported {
+                        warnings.push(CompileWarning::UnreachableCode { span: instruction.span.clone(), custom_label: None });
+                        reported = true;
+                    }
+                    if reachable && matches!(instruction.opcode, Opcode::Exit | Opcode::Ja) {
+                        reachable = false;
+                        reported = false;
+                    }
+                    last = Some(instruction);
+                }
+                _ => {}
+            }
+        }
+        if let Some(last) = last && !matches!(last.opcode, Opcode::Exit | Opcode::Ja) {
+            warnings.push(CompileWarning::MissingExit { span: last.span.clone(), custom_label: None });
+        }
+        warnings.sort_by_key(|warning| warning.span().start);
+        warnings
+    }
+
+    // Functions are the text labels declared with `.type <name>, @function`.
+    // Without an explicit `.size` a function extends to the next function or
+    // global, or the end of .text
+    fn collect_function_symbols(&self, nodes: &[ASTNode], errors: &mut Vec<CompileError>) -> Vec<FunctionSymbol> {
+        let mut globals = Vec::new();
+        let mut sizes = HashMap::new();
+        for node in nodes {
+            match node {
+                ASTNode::GlobalDecl { global_decl } => globals.push(global_decl.get_entry_label()),
+                ASTNode::SizeDecl { size_decl } => { sizes.insert(size_decl.get_name(), size_decl.get_size()); }
+                _ => {}
+            }
+        }
+
+        let mut functions: Vec<FunctionSymbol> = Vec::new();
+        for node in nodes {
+            if let ASTNode::TypeDecl { type_decl } = node {
+                let name = type_decl.get_name();
+                if !self.is_text_label(&name) {
+                    errors.push(CompileError::UndefinedLabel { label: name, span: type_decl.span.clone(), suggestion: None, custom_label: Some("Expected a label in .text".to_string()) });
+                } else if !functions.iter().any(|f| f.name == name) {
+                    functions.push(FunctionSymbol {
+                        offset: self.m_label_offsets[&name],
+                        size: 0,
+                        is_global: globals.contains(&name),
+                        name,
+                    });
+                }
+            }
+        }
+
+        functions.sort_by_key(|f| f.offset);
+        let starts: Vec<u64> = functions.iter().map(|f| f.offset)
+            .chain(globals.iter().filter(|name| self.is_text_label(name)).map(|name| self.m_label_offsets[name]))
+            .collect();
+        for function in &mut functions {
+            function.size = match sizes.get(&function.name) {
+                Some(size) => *size,
+                None => {
+                    let end = starts.iter().filter(|start| **start > function.offset).min().copied().unwrap_or(self.m_accum_offset);
+                    end - function.offset
+                }
+            };
+        }
+        functions
+    }
+
     pub fn parse(&mut self) -> Result<ParseResult, Vec<CompileError>> {
         let mut nodes = Vec::new();
         let mut rodata_nodes = Vec::new();
@@ -623,182 +1328,416 @@ impl Parser {
 
         let mut errors = Vec::new();
 
-        let mut tokens = self.tokens.as_slice();
-
-        // TODO: when parse error occurs, we should probably just jump to the next line
-        // if we're able to error out the scenario where users put 2 instructions in the same line
-        // for now we just continue to the next token
+        // a copy so the token stream can be walked while the parser state is updated
+        let source_tokens = self.tokens.clone();
+        let mut cursor = TokenCursor::new(&source_tokens);
 
-        // TODO: it would be nice if we build a token iterator that can 
-        // 1. peek the next multiple tokens (for detecting patterns)
-        // 2. jump to the next line
-        // 3. continue to the next token
-        while !tokens.is_empty() {
-            match &tokens[0] {
+        // a bad statement is reported once, parsing resumes on the next line
+        while let Some(token) = cursor.peek(0) {
+            if cursor.line_has_error() {
+                cursor.skip_line();
+                continue;
+            }
+            match token {
                 Token::Directive(name, span) => {
                     match name.as_str() {
                         "global" | "globl" => {
-                            match GlobalDecl::parse(tokens) {
+                            match GlobalDecl::parse(cursor.rest()) {
+                                Ok((node, rest)) => {
+                                // other globals are exported functions
+                                if self.m_entry_label.is_none() {
+                                    self.m_entry_label = Some(node.get_entry_label());
+                                }
+                                nodes.push(ASTNode::GlobalDecl { global_decl: node });
+                                cursor.seek(rest);
+                                }
+                                Err(e) => {
+                                    errors.push(e);
+                                    cursor.skip_line();
+                                }
+                            }
+                        }
+                        // the entry point is exported like any other global
+                        "entry" => {
+                            match GlobalDecl::parse(cursor.rest()) {
+                                Ok((node, rest)) if self.m_entry_directive => {
+                                    errors.push(CompileError::InvalidGlobalDecl { span: node.span, custom_label: Some(EXPECTS_ONE_ENTRY.to_string()) });
+                                    cursor.seek(rest);
+                                }
                                 Ok((node, rest)) => {
                                 self.m_entry_label = Some(node.get_entry_label());
+                                self.m_entry_directive = true;
                                 nodes.push(ASTNode::GlobalDecl { global_decl: node });
-                                tokens = rest;
+                                cursor.seek(rest);
                                 }
                                 Err(e) => {
                                     errors.push(e);
-                                    tokens = &tokens[1..];
+                                    cursor.skip_line();
                                 }
                             }
                         }
                         "extern" => {
-                            match ExternDecl::parse(tokens) {
+                            match ExternDecl::parse(cursor.rest()) {
                                 Ok((node, rest)) => {
                                 nodes.push(ASTNode::ExternDecl { extern_decl: node });
-                                tokens = rest;
+                                cursor.seek(rest);
                                 }
                                 Err(e) => {
                                     errors.push(e);
-                                    tokens = &tokens[1..];
+                                    cursor.skip_line();
                                 }
                             }
                         }
                         "rodata" => {
                             nodes.push(ASTNode::RodataDecl { rodata_decl: RodataDecl { span: span.clone() } });
                             rodata_phase = true;
-                            tokens = &tokens[1..];
+                            cursor.advance(1);
                         }
                         "equ" => {
-                            match EquDecl::parse(tokens) {
+                            match EquDecl::parse_with_constmap(cursor.rest(), &self.m_const_map) {
                                 Ok((node, rest)) => {
-                                self.m_const_map.insert(node.get_name(), node.get_val());
+                                if self.define(&node.name, &node.span, false, &mut errors) {
+                                    if let Token::Expression(expr, _) = &node.value {
+                                        let location = if rodata_phase { self.m_accum_offset + self.m_rodata_size } else { self.m_accum_offset };
+                                        self.m_deferred_consts.push((node.get_name(), expr.clone(), location));
+                                    }
+                                    self.m_const_map.insert(node.get_name(), node.value.clone());
+                                }
                                 nodes.push(ASTNode::EquDecl { equ_decl: node });
-                                tokens = rest;
+                                cursor.seek(rest);
+                                }
+                                Err(e) => {
+                                    errors.push(e);
+                                    cursor.skip_line();
+                                }
+                            }
+                        }
+                        "type" => {
+                            match TypeDecl::parse(cursor.rest()) {
+                                Ok((node, rest)) => {
+                                nodes.push(ASTNode::TypeDecl { type_decl: node });
+                                cursor.seek(rest);
                                 }
                                 Err(e) => {
                                     errors.push(e);
-                                    tokens = &tokens[1..];
+                                    cursor.skip_line();
                                 }
                             }
                         }
+                        "size" => {
+                            match SizeDecl::parse(cursor.rest()) {
+                                Ok((node, rest)) => {
+                                nodes.push(ASTNode::SizeDecl { size_decl: node });
+                                cursor.seek(rest);
+                                }
+                                Err(e) => {
+                                    errors.push(e);
+                                    cursor.skip_line();
+                                }
+                            }
+                        }
+                        "align" | "balign" | "p2align" => {
+                            match Align::parse_with_constmap(cursor.rest(), &self.m_const_map) {
+                                Ok((mut align, rest)) => {
+                                    if rodata_phase {
+                                        // rodata is aligned relative to the start of .rodata
+                                        align.fill.get_or_insert(0);
+                                        align.padding = self.m_rodata_size.next_multiple_of(align.alignment) - self.m_rodata_size;
+                                        self.m_rodata_align = self.m_rodata_align.max(align.alignment);
+                                        let offset = self.m_accum_offset + self.m_rodata_size;
+                                        self.m_rodata_size += align.padding;
+                                        rodata_nodes.push(ASTNode::Align { align, offset });
+                                    } else if align.fill.is_some() {
+                                        errors.push(CompileError::InvalidAlignDecl { span: align.span.clone(), custom_label: Some(EXPECTS_NO_TEXT_FILL.to_string()) });
+                                    } else {
+                                        align.padding = self.m_accum_offset.next_multiple_of(align.alignment) - self.m_accum_offset;
+                                        self.m_text_align = self.m_text_align.max(align.alignment);
+                                        let offset = self.m_accum_offset;
+                                        self.m_accum_offset += align.padding;
+                                        nodes.push(ASTNode::Align { align, offset });
+                                    }
+                                    cursor.seek(rest);
+                                }
+                                Err(e) => {
+                                    errors.push(e);
+                                    cursor.skip_line();
+                                }
+                            }
+                        }
+                        name if rodata_phase && ROData::is_data_directive(name) => {
+                            cursor.seek(self.parse_rodata(cursor.rest(), &mut rodata_nodes, &mut errors));
+                        }
                         "section" => {
                             nodes.push(ASTNode::Directive { directive: Directive { name: name.clone(), args: Vec::new(), span: span.clone() } });
-                            tokens = &tokens[1..];
+                            // the section name follows on the same line
+                            cursor.advance(1);
+                            continue;
                         }
                         _ => {
-                            errors.push(CompileError::InvalidDirective { directive: name.clone(), span: span.clone(), custom_label: None });
-                            tokens = &tokens[1..];
+                            let custom_label = ROData::is_data_directive(name).then(|| "Data directives belong in .rodata".to_string());
+                            errors.push(CompileError::InvalidDirective { directive: name.clone(), span: span.clone(), custom_label });
+                            cursor.skip_line();
                         }
                     }
                 }
                 Token::Label(name, span) => {
-                    if rodata_phase {
-                        match ROData::parse(tokens) {
-                            Ok((rodata, rest)) => {
+                    if rodata_phase && matches!(cursor.peek(1), Some(Token::Directive(_, _))) {
+                        cursor.seek(self.parse_rodata(cursor.rest(), &mut rodata_nodes, &mut errors));
+                    } else if rodata_phase {
+                        if self.define(name, span, true, &mut errors) {
                             self.m_label_offsets.insert(name.clone(), self.m_accum_offset + self.m_rodata_size);
-                            self.m_rodata_size += rodata.get_size();
-                            rodata_nodes.push(ASTNode::ROData { rodata, offset: self.m_accum_offset });
-                            tokens = rest;
-                            }
-                            Err(e) => {
-                                errors.push(e);
-                                tokens = &tokens[1..];
-                            }
                         }
+                        rodata_nodes.push(ASTNode::Label { label: Label { name: name.clone(), span: span.clone() } });
+                        // a statement can follow on the same line
+                        cursor.advance(1);
+                        continue;
                     } else {
-                        self.m_label_offsets.insert(name.clone(), self.m_accum_offset);
+                        if self.define(name, span, true, &mut errors) {
+                            self.m_label_offsets.insert(name.clone(), self.m_accum_offset);
+                        }
                         nodes.push(ASTNode::Label { label: Label { name: name.clone(), span: span.clone() } });
-                        tokens = &tokens[1..];
+                        cursor.advance(1);
+                        continue;
                     }
                 }
                 Token::Opcode(_, _) => {
-                    match Instruction::parse_instruction(tokens, &self.m_const_map) {
+                    match Instruction::parse_instruction(cursor.rest(), &self.m_const_map) {
                         Ok((inst, rest)) => {
-                            if inst.needs_relocation() {
-                                self.m_prog_is_static = false;
-                                let (reloc_type, label) = inst.get_relocation_info();
-                                self.m_rel_dyns.add_rel_dyn(self.m_accum_offset, reloc_type, label.clone());
-                                if reloc_type == RelocationType::RSbfSyscall {
-                                    self.m_dynamic_symbols.add_call_target(label.clone(), self.m_accum_offset);
-                                }
+                            if !self.m_version.supports(inst.opcode) {
+                                errors.push(CompileError::UnsupportedOpcode {
+                                    opcode: inst.opcode.to_string(),
+                                    version: self.m_version.to_string(),
+                                    span: inst.span.clone(),
+                                    custom_label: None });
                             }
                             let offset = self.m_accum_offset;
                             self.m_accum_offset += inst.get_size();
                             nodes.push(ASTNode::Instruction { instruction: inst, offset });
-                            tokens = rest;
+                            cursor.seek(rest);
                         }
                         Err(e) => {
                             errors.push(e);
-                            tokens = &tokens[1..];
+                            cursor.skip_line();
                         }
                     }
                 }
+                Token::Newline(_) => {
+                    cursor.advance(1);
+                    continue;
+                }
+                // a misspelled mnemonic lexes as a plain name
+                Token::Identifier(name, span) => {
+                    errors.push(CompileError::UnknownInstruction { instruction: name.clone(), span: span.clone(), custom_label: None });
+                    cursor.skip_line();
+                }
                 _ => {
-                    tokens = &tokens[1..];
+                    let span = token.span().clone();
+                    errors.push(CompileError::UnexpectedToken {
+                        token: self.source_text(&span), span, custom_label: Some(EXPECTS_STATEMENT.to_string()) });
+                    cursor.skip_line();
                 }
             }
+            // one statement per line
+            if let Err(e) = cursor.expect_line_end() {
+                errors.push(e);
+                cursor.skip_line();
+            }
         }
 
-        if !errors.is_empty() {
+        // lines with lexical errors were skipped, labels and constants on
+        // them would show up as undefined
+        let lexing_failed = source_tokens.iter().any(|token| matches!(token, Token::Error(_)));
+        if !errors.is_empty() || lexing_failed {
             return Err(errors);
         }
 
+        // lddw of a name loads the address of a label, relocated unless v3
+        // fixes it, or the value of a constant defined further down. An
+        // address offset by a constant is relocated the same way, the loader
+        // rebases whatever address the instruction holds. Whether an operand
+        // is an address doesn't depend on the layout, which isn't final yet
+        let provisional_consts = self.resolve_deferred_consts(&mut Vec::new());
+        for node in &mut nodes {
+            if let ASTNode::Instruction { instruction, offset } = node
+                && instruction.opcode == Opcode::Lddw {
+                let label = match instruction.operands.last().cloned() {
+                    Some(Token::Identifier(name, span)) if self.m_const_map.contains_key(&name) => {
+                        instruction.operands[1] = Token::Expression(Expr::Symbol(name, span.clone()), span);
+                        None
+                    }
+                    Some(Token::Identifier(name, _)) => Some(name),
+                    Some(Token::Expression(expr, _)) => match expr.evaluate(&|symbol| self.resolve_symbol(symbol, *offset, &provisional_consts)) {
+                        Ok(Resolved::Address(_)) => expr.symbols().into_iter()
+                            .find(|symbol| !matches!(self.m_const_map.get(*symbol), Some(Token::ImmediateValue(_, _))))
+                            .map(str::to_string),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(label) = label && !self.m_version.enable_stricter_elf_headers() {
+                    self.m_prog_is_static = false;
+                    self.m_rel_dyns.add_rel_dyn(*offset, RelocationType::RSbf64Relative, label);
+                }
+            }
+        }
+
+        // Label references in .rodata are addresses that have to be relocated
+        // like the ones loaded with lddw
+        for node in &mut rodata_nodes {
+            if let ASTNode::ROData { rodata, offset } = node {
+                for (idx, arg) in rodata.args.iter_mut().skip(1).enumerate() {
+                    if let Token::Identifier(name, span) = arg.clone() {
+                        if self.m_const_map.contains_key(&name) {
+                            *arg = Token::Expression(Expr::Symbol(name, span.clone()), span);
+                        } else if !self.m_version.enable_stricter_elf_headers() {
+                            self.m_prog_is_static = false;
+                            self.m_rel_dyns.add_rel_dyn(*offset + idx as u64 * 8, RelocationType::RSbf64Relative, name);
+                        }
+                    }
+                }
+            }
+        }
+
+        let externs: Vec<String> = nodes.iter()
+            .filter_map(|node| match node {
+                ASTNode::ExternDecl { extern_decl } => Some(&extern_decl.args),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|arg| match arg {
+                Token::Identifier(name, _) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        // Calls to labels defined in .text are internal function calls,
+        // anything else has to be a syscall, resolved by the loader unless
+        // syscalls are static
+        for node in &nodes {
+            if let ASTNode::Instruction { instruction, offset } = node
+                && instruction.opcode == Opcode::Call
+                && let Some(Token::Identifier(name, span)) = instruction.operands.first()
+                && !self.is_text_label(name) {
+                if !is_syscall(name) && !externs.contains(name) {
+                    errors.push(CompileError::UndefinedLabel {
+                        label: name.clone(), span: span.clone(), suggestion: None, custom_label: Some(EXPECTS_FUNCTION_OR_SYSCALL.to_string()) });
+                } else if !self.m_version.static_syscalls() {
+                    self.m_prog_is_static = false;
+                    let (reloc_type, label) = instruction.get_relocation_info();
+                    self.m_rel_dyns.add_rel_dyn(*offset, reloc_type, label.clone());
+                    self.m_dynamic_symbols.add_call_target(label, *offset);
+                }
+            }
+        }
+
+        let consts = self.resolve_deferred_consts(&mut errors);
+
         // Second pass to resolve labels
         for node in &mut nodes {
-            match node {
-                ASTNode::Instruction { instruction: Instruction { opcode, operands, .. }, offset, .. } => {
-                    // For jump instructions, replace label operands with relative offsets
-                    if *opcode == Opcode::Ja || *opcode == Opcode::JeqImm || *opcode == Opcode::JgtImm || *opcode == Opcode::JgeImm 
-                    || *opcode == Opcode::JltImm || *opcode == Opcode::JleImm || *opcode == Opcode::JsetImm || *opcode == Opcode::JneImm     
-                    || *opcode == Opcode::JsgtImm || *opcode == Opcode::JsgeImm || *opcode == Opcode::JsltImm || *opcode == Opcode::JsleImm
-                    || *opcode == Opcode::JeqReg || *opcode == Opcode::JgtReg || *opcode == Opcode::JgeReg || *opcode == Opcode::JltReg 
-                    || *opcode == Opcode::JleReg || *opcode == Opcode::JsetReg || *opcode == Opcode::JneReg || *opcode == Opcode::JsgtReg 
-                    || *opcode == Opcode::JsgeReg || *opcode == Opcode::JsltReg || *opcode == Opcode::JsleReg {
-                        if let Some(Token::Identifier(label, span)) = operands.last() {
-                            let label = label.clone(); // Clone early to avoid borrow conflict
-                            if let Some(target_offset) = self.m_label_offsets.get(&label) {
-                                let rel_offset = (*target_offset as i64 - *offset as i64) / 8 - 1;
-                                // Replace label with immediate value
-                                let last_idx = operands.len() - 1;
-                                operands[last_idx] = Token::ImmediateValue(ImmediateValue::Int(rel_offset), span.clone());
-                            }
+            if let ASTNode::Instruction { instruction: Instruction { opcode, operands, .. }, offset, .. } = node {
+                for operand in operands.iter_mut() {
+                    if let Err(e) = self.resolve_expression(operand, *offset, &consts, *opcode == Opcode::Lddw) {
+                        errors.push(e);
+                    }
+                }
+                // For jump instructions, replace label operands with relative offsets
+                if opcode.is_jump()
+                && let Some(Token::Identifier(label, span)) = operands.last() {
+                    if let Some(target_offset) = self.m_label_offsets.get(label) {
+                        let rel_offset = (*target_offset as i64 - *offset as i64) / 8 - 1;
+                        // Replace label with immediate value
+                        let last_idx = operands.len() - 1;
+                        operands[last_idx] = Token::ImmediateValue(ImmediateValue::Int(rel_offset), span.clone());
+                    } else {
+                        errors.push(CompileError::UndefinedLabel { label: label.clone(), span: span.clone(), suggestion: None, custom_label: None });
+                    }
+                }
+                // the offset, whether computed or written out, has to fit the instruction
+                if opcode.is_jump()
+                && let Some(Token::ImmediateValue(rel_offset, span)) = operands.last()
+                && i16::try_from(rel_offset.to_i128()).is_err() {
+                    errors.push(CompileError::BranchOutOfRange { offset: rel_offset.to_i64(), span: span.clone(), custom_label: None });
+                }
+                // Internal calls are pc-relative, same as jumps
+                if *opcode == Opcode::Call
+                && let Some(Token::Identifier(label, span)) = operands.first()
+                && self.is_text_label(label) {
+                    let target_offset = self.m_label_offsets[label];
+                    let rel_offset = (target_offset as i64 - *offset as i64) / 8 - 1;
+                    operands[0] = Token::ImmediateValue(ImmediateValue::Int(rel_offset), span.clone());
+                }
+                if *opcode == Opcode::Lddw
+                && let Some(Token::Identifier(name, span)) = operands.last() {
+                    let label = name.clone();
+                    if let Some(target_offset) = self.m_label_offsets.get(&label) {
+                        let abs_offset = self.label_address(*target_offset) as i64;
+                        // Replace label with immediate value
+                        let last_idx = operands.len() - 1;
+                        operands[last_idx] = Token::ImmediateValue(ImmediateValue::Addr(abs_offset), span.clone());
+                    }  else {
+                        errors.push(CompileError::UndefinedLabel { label: name.clone(), span: span.clone(), suggestion: None, custom_label: None });
+                    }
+                }
+            }
+        }
+
+        // Label references and expressions in .rodata
+        for node in &mut rodata_nodes {
+            if let ASTNode::ROData { rodata, offset } = node {
+                let Some(Token::Directive(directive, _)) = rodata.args.first().cloned() else { bug!("ROData not a valid directive") };
+                for arg in rodata.args.iter_mut() {
+                    if let Token::Expression(_, _) = arg {
+                        let resolved = self.resolve_expression(arg, *offset, &consts, false).and_then(|_| match arg {
+                            Token::ImmediateValue(value, span) => check_data_value(&directive, value, span),
+                            _ => Ok(()),
+                        });
+                        if let Err(e) = resolved {
+                            errors.push(e);
                         }
                     }
-                    if *opcode == Opcode::Lddw {
-                        if let Some(Token::Identifier(name, span)) = operands.last() {
-                            let label = name.clone();
-                            if let Some(target_offset) = self.m_label_offsets.get(&label) {
-                                let ph_count = if self.m_prog_is_static { 1 } else { 3 };
-                                let ph_offset = 64 + (ph_count as u64 * 56) as i64;
-                                let abs_offset = *target_offset as i64 + ph_offset;
-                                // Replace label with immediate value
-                                let last_idx = operands.len() - 1;
-                                operands[last_idx] = Token::ImmediateValue(ImmediateValue::Addr(abs_offset), span.clone());
-                            }  else {
-                                errors.push(CompileError::UndefinedLabel { label: name.clone(), span: span.clone(), custom_label: None });
-                            }
+                    if let Token::Identifier(name, span) = arg {
+                        if let Some(target_offset) = self.m_label_offsets.get(name) {
+                            let address = self.label_address(*target_offset);
+                            // the v0 loader reads the address to relocate from the upper half
+                            let value = if self.m_version == SbpfVersion::V0 { address << 32 } else { address };
+                            *arg = Token::ImmediateValue(ImmediateValue::Addr(value as i64), span.clone());
+                        } else {
+                            errors.push(CompileError::UndefinedLabel { label: name.clone(), span: span.clone(), suggestion: None, custom_label: None });
                         }
                     }
                 }
-                _ => {}
             }
         }
 
+        self.check_operand_ranges(&nodes, &mut errors);
+        self.check_registers(&nodes, &mut errors);
+
+        let function_symbols = self.collect_function_symbols(&nodes, &mut errors);
+
         // Set entry point offset if an entry label was specified
-        if let Some(entry_label) = &self.m_entry_label {
-            if let Some(offset) = self.m_label_offsets.get(entry_label) {
-                self.m_dynamic_symbols.add_entry_point(entry_label.clone(), *offset);
-            }
+        if let Some(entry_label) = &self.m_entry_label
+            && let Some(offset) = self.m_label_offsets.get(entry_label) {
+            self.m_dynamic_symbols.add_entry_point(entry_label.clone(), *offset);
         }
 
         if !errors.is_empty() {
-            return Err(errors);
+            self.add_suggestions(&mut errors, &externs);
+            Err(errors)
         } else {
+            let warnings = self.collect_warnings(&nodes);
+            let mut code_section = CodeSection::new(nodes, self.m_accum_offset, self.m_file.as_ref().unwrap(), self.m_version);
+            code_section.set_align(self.m_text_align);
+            let mut data_section = DataSection::new(rodata_nodes, self.m_rodata_size);
+            data_section.set_align(self.m_rodata_align);
             Ok(ParseResult {
-                code_section: CodeSection::new(nodes, self.m_accum_offset, self.m_file.as_ref().unwrap()),
-                data_section: DataSection::new(rodata_nodes, self.m_rodata_size),
+                code_section,
+                data_section,
                 dynamic_symbols: DynamicSymbolMap::copy(&self.m_dynamic_symbols),
                 relocation_data: RelDynMap::copy(&self.m_rel_dyns),
+                function_symbols,
+                version: self.m_version,
                 prog_is_static: self.m_prog_is_static,
+                warnings,
             })
         }
     }
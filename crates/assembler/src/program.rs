@@ -1,6 +1,6 @@
 use crate::header::ElfHeader;
 use crate::header::ProgramHeader;
-use crate::section::{Section, NullSection, DynamicSection, ShStrTabSection, SectionType, DynStrSection, DynSymSection, RelDynSection};
+use crate::section::{Section, NullSection, DynamicSection, ShStrTabSection, SectionType, DynStrSection, DynSymSection, RelDynSection, SymTabSection, StrTabSection};
 use crate::dynsym::{DynamicSymbol, RelDyn, RelocationType};
 use crate::parser::ParseResult;
 use crate::debuginfo::DebugInfo;
@@ -19,30 +19,66 @@ pub struct Program {
 impl Program {
     pub fn from_parse_result(
         ParseResult {
-            code_section,
-            data_section,
+            mut code_section,
+            mut data_section,
             dynamic_symbols,
             relocation_data,
+            function_symbols,
+            version,
             prog_is_static: is_static,
+            warnings: _,
         }: ParseResult,
     ) -> Self {
         let mut elf_header = ElfHeader::new();
+        elf_header.e_flags = version.e_flags();
+        let strict = version.enable_stricter_elf_headers();
+        if strict {
+            elf_header.e_machine = ElfHeader::SBPF_MACHINE;
+        }
        
-        let ph_count = if is_static { 1 } else { 3 };
+        // v3 expects exactly the bytecode and rodata segments
+        let ph_count = if strict { 2 } else if is_static { 1 } else { 3 };
         elf_header.e_phnum = ph_count;
         
         // Calculate base offset after ELF header and program headers
-        let mut current_offset = 64 + (ph_count as u64 * 56); // 64 bytes ELF header, 56 bytes per program header
-        elf_header.e_entry = current_offset;
-
-        // Create program headers vector starting with the Read+Execute header
-        let mut program_headers = vec![
-            ProgramHeader::new_load(
-                elf_header.e_entry,
-                code_section.size() + data_section.size(),
-                true,   // executable
-            )
-        ];
+        let text_size = code_section.size();
+        let (text_offset, rodata_offset) = if strict {
+            let text_offset = 64 + (ph_count as u64 * 56); // 64 bytes ELF header, 56 bytes per program header
+            (text_offset, text_offset + text_size)
+        } else {
+            Self::legacy_section_offsets(ph_count as u64, text_size, code_section.get_align(), data_section.get_align())
+        };
+        // data relocations live in .rodata, past the end of .text
+        let file_offset = |offset: u64| if offset < text_size { text_offset + offset } else { rodata_offset + offset - text_size };
+        let mut current_offset = text_offset;
+        let text_vaddr = if strict { ProgramHeader::MM_BYTECODE_START } else { text_offset };
+        let entry_offset = dynamic_symbols.get_entry_points().first().map(|(_, offset)| *offset).unwrap_or(0);
+        elf_header.e_entry = text_vaddr + entry_offset;
+
+        let mut program_headers = if strict {
+            vec![
+                ProgramHeader::new_load_at(text_offset, text_vaddr, code_section.size(), true),
+                ProgramHeader::new_load_at(
+                    rodata_offset,
+                    ProgramHeader::MM_RODATA_START,
+                    data_section.size(),
+                    false,
+                ),
+            ]
+        } else {
+            // Read+Execute header covering both .text and .rodata
+            vec![
+                ProgramHeader::new_load(
+                    text_offset,
+                    if data_section.size() > 0 { rodata_offset + data_section.size() - text_offset } else { text_size },
+                    true,   // executable
+                )
+            ]
+        };
+        if strict {
+            code_section.set_vaddr(text_vaddr);
+            data_section.set_vaddr(ProgramHeader::MM_RODATA_START);
+        }
 
         // Create a vector of sections
         let mut sections = Vec::new();
@@ -60,8 +96,8 @@ impl Program {
         // Data section
         if data_section.size() > 0 {
             let mut rodata_section = SectionType::Data(data_section);
-            rodata_section.set_offset(current_offset);
-            current_offset += rodata_section.size();
+            rodata_section.set_offset(rodata_offset);
+            current_offset = rodata_offset + rodata_section.size();
             section_names.push(rodata_section.name().to_string());
             sections.push(rodata_section);
         }
@@ -79,12 +115,24 @@ impl Program {
             dyn_syms.push(DynamicSymbol::new(0, 0, 0, 0, 0, 0));
 
             // all symbols handled right now are all global symbols
-            for (name, _) in dynamic_symbols.get_entry_points() {
+            for (name, offset) in dynamic_symbols.get_entry_points() {
+                let (info, size) = match function_symbols.iter().find(|f| f.name == name) {
+                    Some(function) => (0x12, function.size),
+                    None => (0x10, 0),
+                };
                 symbol_names.push(name.clone());
-                dyn_syms.push(DynamicSymbol::new(dyn_str_offset as u32, 0x10, 0, 1, elf_header.e_entry, 0));
+                dyn_syms.push(DynamicSymbol::new(dyn_str_offset as u32, info, 0, 1, text_offset + offset, size));
                 dyn_str_offset += name.len() + 1;
             }
 
+            // exported functions other than the entrypoint
+            let exported: Vec<_> = function_symbols.iter().filter(|f| f.is_global && !symbol_names.contains(&f.name)).collect();
+            for function in exported {
+                symbol_names.push(function.name.clone());
+                dyn_syms.push(DynamicSymbol::new(dyn_str_offset as u32, 0x12, 0, 1, text_offset + function.offset, function.size));
+                dyn_str_offset += function.name.len() + 1;
+            }
+
             for (name, _) in dynamic_symbols.get_call_targets() {
                 symbol_names.push(name.clone());                 
                 dyn_syms.push(DynamicSymbol::new(dyn_str_offset as u32, 0x10, 0, 0, 0, 0));
@@ -96,13 +144,13 @@ impl Program {
             for (offset, rel_type, name) in relocation_data.get_rel_dyns() {
                 if rel_type == RelocationType::RSbfSyscall {
                     if let Some(index) = symbol_names.iter().position(|n| *n == name) {
-                        rel_dyns.push(RelDyn::new(offset + elf_header.e_entry, rel_type as u64, index as u64 + 1));
+                        rel_dyns.push(RelDyn::new(file_offset(offset), rel_type as u64, index as u64 + 1));
                     } else {
                         panic!("Symbol {} not found in symbol_names", name);
                     }
                 } else if rel_type == RelocationType::RSbf64Relative {
                     rel_count += 1;
-                    rel_dyns.push(RelDyn::new(offset + elf_header.e_entry, rel_type as u64, 0));
+                    rel_dyns.push(RelDyn::new(file_offset(offset), rel_type as u64, 0));
                 }
             }
             let mut dynamic_section = SectionType::Dynamic(DynamicSection::new((section_names.iter().map(|name| name.len() + 1).sum::<usize>() + 1) as u32));
@@ -128,7 +176,18 @@ impl Program {
             current_offset += rel_dyn_section.size();
             section_names.push(rel_dyn_section.name().to_string());
 
+            // .dynamic, .dynsym, .dynstr and .rel.dyn are pushed in this order
+            let dynsym_index = sections.len() as u32 + 1;
+            let dynstr_index = dynsym_index + 1;
+            if let SectionType::DynSym(ref mut dynsym_section) = dynsym_section {
+                dynsym_section.set_link(dynstr_index);
+            }
+            if let SectionType::RelDyn(ref mut rel_dyn_section) = rel_dyn_section {
+                rel_dyn_section.set_link(dynsym_index);
+            }
+
             if let SectionType::Dynamic(ref mut dynamic_section) = dynamic_section {
+                dynamic_section.set_link(dynstr_index);
                 dynamic_section.set_rel_offset(rel_dyn_section.offset());
                 dynamic_section.set_rel_size(rel_dyn_section.size());
                 dynamic_section.set_dynsym_offset(dynsym_section.offset());
@@ -136,10 +195,6 @@ impl Program {
                 dynamic_section.set_dynstr_size(dynstr_section.size());
             }
 
-            let mut shstrtab_section = SectionType::ShStrTab(ShStrTabSection::new((section_names.iter().map(|name| name.len() + 1).sum::<usize>() + 1) as u32, section_names));
-            shstrtab_section.set_offset(current_offset);
-            current_offset += shstrtab_section.size();
-
             let ro_header = ProgramHeader::new_load(
                 dynsym_section.offset(),
                 dynsym_section.size() + dynstr_section.size() + rel_dyn_section.size(),
@@ -155,23 +210,59 @@ impl Program {
             sections.push(dynsym_section);
             sections.push(dynstr_section);
             sections.push(rel_dyn_section);
-            sections.push(shstrtab_section);
 
             program_headers.push(ro_header);
             program_headers.push(dynamic_header);
-        } else {
-            // Create a vector of section names
-            let mut section_names = Vec::new();
-            for section in &sections {
-                section_names.push(section.name().to_string());
+        }
+
+        // Static symbol table, only emitted when functions are declared
+        if !function_symbols.is_empty() {
+            let entry_label = dynamic_symbols.get_entry_points().first().map(|(name, _)| name.clone());
+            let mut symbol_names = Vec::new();
+            let mut syms = vec![DynamicSymbol::new(0, 0, 0, 0, 0, 0)];
+            let mut str_offset = 1;
+
+            // local symbols have to precede global ones
+            let locals = function_symbols.iter().filter(|f| !f.is_global && Some(&f.name) != entry_label.as_ref());
+            for function in locals {
+                symbol_names.push(function.name.clone());
+                syms.push(DynamicSymbol::new(str_offset as u32, 0x02, 0, 1, text_vaddr + function.offset, function.size));
+                str_offset += function.name.len() + 1;
+            }
+            let first_global = syms.len() as u32;
+            if let Some(name) = &entry_label && !function_symbols.iter().any(|f| f.name == *name) {
+                symbol_names.push(name.clone());
+                syms.push(DynamicSymbol::new(str_offset as u32, 0x10, 0, 1, elf_header.e_entry, 0));
+                str_offset += name.len() + 1;
+            }
+            let globals = function_symbols.iter().filter(|f| f.is_global || Some(&f.name) == entry_label.as_ref());
+            for function in globals {
+                symbol_names.push(function.name.clone());
+                syms.push(DynamicSymbol::new(str_offset as u32, 0x12, 0, 1, text_vaddr + function.offset, function.size));
+                str_offset += function.name.len() + 1;
             }
 
-            let mut shstrtab_section = ShStrTabSection::new(section_names.iter().map(|name| name.len() + 1).sum::<usize>() as u32, section_names);
-            shstrtab_section.set_offset(current_offset);
-            current_offset += shstrtab_section.size();
-            sections.push(SectionType::ShStrTab(shstrtab_section));
+            // .strtab directly follows .symtab
+            let strtab_index = sections.len() as u32 + 1;
+            let mut symtab_section = SectionType::SymTab(SymTabSection::new((section_names.iter().map(|name| name.len() + 1).sum::<usize>() + 1) as u32, syms, strtab_index, first_global));
+            symtab_section.set_offset(current_offset);
+            current_offset += symtab_section.size();
+            section_names.push(symtab_section.name().to_string());
+
+            let mut strtab_section = SectionType::StrTab(StrTabSection::new((section_names.iter().map(|name| name.len() + 1).sum::<usize>() + 1) as u32, symbol_names));
+            strtab_section.set_offset(current_offset);
+            current_offset += strtab_section.size();
+            section_names.push(strtab_section.name().to_string());
+
+            sections.push(symtab_section);
+            sections.push(strtab_section);
         }
 
+        let mut shstrtab_section = SectionType::ShStrTab(ShStrTabSection::new((section_names.iter().map(|name| name.len() + 1).sum::<usize>() + 1) as u32, section_names));
+        shstrtab_section.set_offset(current_offset);
+        current_offset += shstrtab_section.size();
+        sections.push(shstrtab_section);
+
         // Update section header offset in ELF header
         let padding = (8 - (current_offset % 8)) % 8;
         elf_header.e_shoff = current_offset + padding;
@@ -186,6 +277,13 @@ impl Program {
         }
     }
     
+    // Legacy programs are loaded at their file offsets, .text right after the elf
+    // and program headers and .rodata right after .text, each moved up to its alignment
+    pub fn legacy_section_offsets(ph_count: u64, text_size: u64, text_align: u64, rodata_align: u64) -> (u64, u64) {
+        let text_offset = (64 + ph_count * 56).next_multiple_of(text_align);
+        (text_offset, (text_offset + text_size).next_multiple_of(rodata_align))
+    }
+
     pub fn emit_bytecode(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         
@@ -197,8 +295,11 @@ impl Program {
             bytes.extend(ph.bytecode());
         }
 
-        // Emit sections
+        // Emit sections, zero filling the gaps left by alignment
         for section in &self.sections {
+            if (bytes.len() as u64) < section.offset() {
+                bytes.resize(section.offset() as usize, 0);
+            }
             bytes.extend(section.bytecode());
         }
 
@@ -4,6 +4,7 @@ use crate::dynsym::DynamicSymbol;
 use crate::dynsym::RelDyn;
 use crate::lexer::Token;
 use crate::debuginfo::DebugInfo;
+use crate::version::SbpfVersion;
 use std::collections::HashMap;
 use crate::astnode::ROData;
 use codespan_reporting::files::SimpleFile;
@@ -37,14 +38,18 @@ pub struct CodeSection {
     offset: u64,
     line_map: HashMap<u64, usize>,
     debug_map: HashMap<u64, DebugInfo>,
+    version: SbpfVersion,
+    // loaded at the file offset unless placed at a fixed address
+    vaddr: Option<u64>,
+    align: u64,
 }
 
 impl CodeSection {
-    pub fn new(nodes: Vec<ASTNode>, size: u64, file: &SimpleFile<String, String>) -> Self {
+    pub fn new(nodes: Vec<ASTNode>, size: u64, file: &SimpleFile<String, String>, version: SbpfVersion) -> Self {
         let line_map = HashMap::new();
         let mut debug_map = HashMap::new();
         for node in &nodes {
-            if let Some((_, node_debug_map)) = node.bytecode_with_debug_map(Some(file)) {
+            if let Some((_, node_debug_map)) = node.bytecode_with_debug_map(Some(file), version) {
                 debug_map.extend(node_debug_map);
             }
         }
@@ -55,6 +60,9 @@ impl CodeSection {
             offset: 0,
             line_map,
             debug_map,
+            version,
+            vaddr: None,
+            align: 4,
         }
     }
 
@@ -82,6 +90,18 @@ impl CodeSection {
         self.offset = offset;
     }
 
+    pub fn set_vaddr(&mut self, vaddr: u64) {
+        self.vaddr = Some(vaddr);
+    }
+
+    // the strictest alignment requested in the section wins
+    pub fn set_align(&mut self, align: u64) {
+        self.align = self.align.max(align);
+    }
+
+    pub fn get_align(&self) -> u64 {
+        self.align
+    }
 
     pub fn section_header_bytecode(&self) -> Vec<u8> {
         let flags = SectionHeader::SHF_ALLOC | SectionHeader::SHF_EXECINSTR;
@@ -89,12 +109,12 @@ impl CodeSection {
             1,
             SectionHeader::SHT_PROGBITS,
             flags,
-            self.offset,
+            self.vaddr.unwrap_or(self.offset),
             self.offset,
             self.size,
             0,
             0,
-            4,
+            self.align,
             0
         ).bytecode()
     }
@@ -108,7 +128,7 @@ impl Section for CodeSection {
     fn bytecode(&self) -> Vec<u8> {
         let mut bytecode = Vec::new();
         for node in &self.nodes {
-            if let Some(node_bytes) = node.bytecode() {
+            if let Some(node_bytes) = node.bytecode(self.version) {
                 bytecode.extend(node_bytes);
             }
         }
@@ -127,6 +147,8 @@ pub struct DataSection {
     nodes: Vec<ASTNode>,
     size: u64,
     offset: u64,
+    vaddr: Option<u64>,
+    align: u64,
     // line_map: HashMap<u64, usize>,
     // debug_map: HashMap<usize, DebugInfo>,
 }
@@ -149,6 +171,8 @@ impl DataSection {
             nodes,
             size,
             offset: 0,
+            vaddr: None,
+            align: 1,
             // line_map,
             // debug_map,
         }
@@ -174,13 +198,24 @@ impl DataSection {
         self.offset = offset;
     }
 
+    pub fn set_vaddr(&mut self, vaddr: u64) {
+        self.vaddr = Some(vaddr);
+    }
+
+    pub fn set_align(&mut self, align: u64) {
+        self.align = self.align.max(align);
+    }
+
+    pub fn get_align(&self) -> u64 {
+        self.align
+    }
+
     pub fn rodata(&self) -> Vec<(String, usize, String)> {
         let mut ro_data_labels = Vec::new();
         for node in &self.nodes {    
-            if let ASTNode::ROData { rodata: ROData { name, args, .. }, offset } = node {
-                if let Some(Token::StringLiteral(str_literal, _)) = args.get(1) {
-                    ro_data_labels.push((name.clone(), offset.clone() as usize, str_literal.clone()));
-                }
+            if let ASTNode::ROData { rodata: ROData { name: Some(name), args, .. }, offset } = node
+                && let Some(Token::StringLiteral(str_literal, _)) = args.get(1) {
+                ro_data_labels.push((name.clone(), *offset as usize, String::from_utf8_lossy(str_literal).into_owned()));
             }
         }
         ro_data_labels
@@ -192,12 +227,12 @@ impl DataSection {
             7,
             SectionHeader::SHT_PROGBITS,
             flags,
-            self.offset,
+            self.vaddr.unwrap_or(self.offset),
             self.offset,
             self.size,
             0,
             0,
-            1,
+            self.align,
             0
         ).bytecode()
     }
@@ -215,8 +250,10 @@ impl Section for DataSection {
     fn bytecode(&self) -> Vec<u8> {
         let mut bytecode = Vec::new();
         for node in &self.nodes {
-            if let Some(node_bytes) = node.bytecode() {
-                bytecode.extend(node_bytes);
+            match node {
+                ASTNode::ROData { rodata, .. } => bytecode.extend(rodata.bytecode()),
+                ASTNode::Align { align, .. } => bytecode.extend(align.bytecode()),
+                _ => {}
             }
         }
         // Add padding to make size multiple of 8
@@ -234,6 +271,12 @@ pub struct NullSection {
     offset: u64,
 }
 
+impl Default for NullSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NullSection {
     pub fn new() -> Self {
         Self {
@@ -359,6 +402,7 @@ pub struct DynamicSection {
     dynsym_offset: u64,
     dynstr_offset: u64,
     dynstr_size: u64,
+    link: u32,
 }
 
 impl DynamicSection {
@@ -373,9 +417,15 @@ impl DynamicSection {
             dynsym_offset: 0,
             dynstr_offset: 0,
             dynstr_size: 0,
+            link: 0,
         }
     }
 
+    // section index of .dynstr
+    pub fn set_link(&mut self, link: u32) {
+        self.link = link;
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -412,7 +462,7 @@ impl DynamicSection {
             self.offset,
             self.offset,
             self.size(),
-            5,
+            self.link,
             0,
             8,
             16
@@ -447,7 +497,7 @@ impl Section for DynamicSection {
         
         // DT_RELCOUNT: number of relative relocation entries
         if self.rel_count > 0 {
-            bytes.extend_from_slice(&0x6fffff_fa_u64.to_le_bytes());
+            bytes.extend_from_slice(&0x6fff_fffa_u64.to_le_bytes());
             bytes.extend_from_slice(&self.rel_count.to_le_bytes());
         }
         
@@ -480,9 +530,9 @@ impl Section for DynamicSection {
 
     fn size(&self) -> u64 {
         if self.rel_count > 0 {
-            return 11 * 16;
+            11 * 16
         } else {
-            return 10 * 16;
+            10 * 16
         }
     }
 }
@@ -567,6 +617,7 @@ pub struct DynSymSection {
     name_offset: u32,
     offset: u64,
     symbols: Vec<DynamicSymbol>,
+    link: u32,
 }
 
 impl DynSymSection {
@@ -576,9 +627,15 @@ impl DynSymSection {
             name_offset,
             offset: 0,
             symbols,
+            link: 0,
         }
     }
 
+    // section index of .dynstr
+    pub fn set_link(&mut self, link: u32) {
+        self.link = link;
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -592,7 +649,7 @@ impl DynSymSection {
             self.offset,
             self.offset,
             self.size(),
-            5,
+            self.link,
             1,
             8,
             24
@@ -621,12 +678,146 @@ impl Section for DynSymSection {
     
 }   
 
+#[derive(Debug)]
+pub struct SymTabSection {
+    name: String,
+    name_offset: u32,
+    offset: u64,
+    symbols: Vec<DynamicSymbol>,
+    strtab_index: u32,
+    first_global: u32,
+}
+
+impl SymTabSection {
+    pub fn new(name_offset: u32, symbols: Vec<DynamicSymbol>, strtab_index: u32, first_global: u32) -> Self {
+        Self {
+            name: String::from(".symtab"),
+            name_offset,
+            offset: 0,
+            symbols,
+            strtab_index,
+            first_global,
+        }
+    }
+
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    pub fn section_header_bytecode(&self) -> Vec<u8> {
+        SectionHeader::new(
+            self.name_offset,
+            SectionHeader::SHT_SYMTAB,
+            0,
+            0,
+            self.offset,
+            self.size(),
+            self.strtab_index,
+            self.first_global,
+            8,
+            24
+        ).bytecode()
+    }
+}
+
+impl Section for SymTabSection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size(&self) -> u64 {
+        // Each symbol entry is 24 bytes
+        (self.symbols.len() as u64) * 24
+    }
+
+    fn bytecode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for symbol in &self.symbols {
+            bytes.extend(symbol.bytecode());
+        }
+        bytes
+    }
+}
+
+#[derive(Debug)]
+pub struct StrTabSection {
+    name: String,
+    name_offset: u32,
+    symbol_names: Vec<String>,
+    offset: u64,
+}
+
+impl StrTabSection {
+    pub fn new(name_offset: u32, symbol_names: Vec<String>) -> Self {
+        Self {
+            name: String::from(".strtab"),
+            name_offset,
+            symbol_names,
+            offset: 0,
+        }
+    }
+
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    pub fn section_header_bytecode(&self) -> Vec<u8> {
+        SectionHeader::new(
+            self.name_offset,
+            SectionHeader::SHT_STRTAB,
+            0,
+            0,
+            self.offset,
+            self.size(),
+            0,
+            0,
+            1,
+            0
+        ).bytecode()
+    }
+}
+
+impl Section for StrTabSection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bytecode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // First byte is null
+        bytes.push(0);
+
+        // Add each symbol name with null terminator
+        for name in &self.symbol_names {
+            bytes.extend(name.as_bytes());
+            bytes.push(0); // null terminator
+        }
+        // add padding to make size multiple of 8
+        while bytes.len() % 8 != 0 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn size(&self) -> u64 {
+        let mut size = 1 + self.symbol_names.iter()
+            .map(|name| name.len() + 1)
+            .sum::<usize>();
+        // add padding to make size multiple of 8
+        while size % 8 != 0 {
+            size += 1;
+        }
+        size as u64
+    }
+}
+
 #[derive(Debug)]
 pub struct RelDynSection {
     name: String,
     name_offset: u32,
     offset: u64,
     entries: Vec<RelDyn>,
+    link: u32,
 }
 
 impl RelDynSection {
@@ -636,9 +827,15 @@ impl RelDynSection {
             name_offset,
             offset: 0,
             entries,
+            link: 0,
         }
     }
 
+    // section index of .dynsym
+    pub fn set_link(&mut self, link: u32) {
+        self.link = link;
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -656,7 +853,7 @@ impl RelDynSection {
             self.offset,
             self.offset,
             self.size(),
-            4,
+            self.link,
             0,
             8,
             16
@@ -695,6 +892,8 @@ pub enum SectionType {
     DynSym(DynSymSection),
     Default(NullSection),
     RelDyn(RelDynSection),
+    SymTab(SymTabSection),
+    StrTab(StrTabSection),
 }
 
 impl SectionType {
@@ -708,6 +907,8 @@ impl SectionType {
             SectionType::DynSym(ds) => &ds.name,
             SectionType::Default(ds) => &ds.name,
             SectionType::RelDyn(ds) => &ds.name,
+            SectionType::SymTab(ss) => &ss.name,
+            SectionType::StrTab(ss) => &ss.name,
         }
     }
 
@@ -721,6 +922,8 @@ impl SectionType {
             SectionType::DynSym(ds) => ds.bytecode(),
             SectionType::Default(ds) => ds.bytecode(),
             SectionType::RelDyn(ds) => ds.bytecode(),
+            SectionType::SymTab(ss) => ss.bytecode(),
+            SectionType::StrTab(ss) => ss.bytecode(),
         }
     }
 
@@ -734,6 +937,8 @@ impl SectionType {
             SectionType::DynSym(ds) => ds.size(),
             SectionType::Default(ds) => ds.size(),
             SectionType::RelDyn(ds) => ds.size(),
+            SectionType::SymTab(ss) => ss.size(),
+            SectionType::StrTab(ss) => ss.size(),
         }
     }
 
@@ -747,6 +952,8 @@ impl SectionType {
             SectionType::DynSym(ds) => ds.section_header_bytecode(),
             SectionType::Default(ds) => ds.section_header_bytecode(),
             SectionType::RelDyn(ds) => ds.section_header_bytecode(),
+            SectionType::SymTab(ss) => ss.section_header_bytecode(),
+            SectionType::StrTab(ss) => ss.section_header_bytecode(),
         }
     }
 
@@ -759,6 +966,8 @@ impl SectionType {
             SectionType::DynStr(ds) => ds.set_offset(offset),
             SectionType::DynSym(ds) => ds.set_offset(offset),
             SectionType::RelDyn(ds) => ds.set_offset(offset),
+            SectionType::SymTab(ss) => ss.set_offset(offset),
+            SectionType::StrTab(ss) => ss.set_offset(offset),
             SectionType::Default(_) => (), // NullSection doesn't need offset
         }
     }
@@ -773,6 +982,8 @@ impl SectionType {
             SectionType::DynSym(ds) => ds.offset,
             SectionType::Default(ns) => ns.offset,
             SectionType::RelDyn(rs) => rs.offset,
+            SectionType::SymTab(ss) => ss.offset,
+            SectionType::StrTab(ss) => ss.offset,
         }
     }
 }
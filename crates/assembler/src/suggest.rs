@@ -0,0 +1,35 @@
+// "Did you mean" suggestions for misspelled names
+
+// The closest candidate, if it is close enough to be a likely typo. An exact
+// match isn't a suggestion
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+    // on a tie a swap beats adding or dropping characters
+    let (distance, candidate) = candidates.into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .min_by_key(|(distance, candidate)| (*distance, candidate.len().abs_diff(name.len())))?;
+    (1..=max_distance).contains(&distance).then_some(candidate)
+}
+
+// Optimal string alignment distance, a swap of two neighbouring characters
+// counts as one edit like in `ldxwd` for `ldxdw`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
@@ -0,0 +1,1346 @@
+use crate::astnode::ASTNode;
+use crate::errors::CompileError;
+use crate::lexer::{ImmediateValue, Token};
+use crate::parser::ParseResult;
+use crate::section::Section;
+use crate::syscalls::murmur3_32;
+use crate::{Program, SbpfVersion, parse_source, tokenize};
+use codespan_reporting::files::SimpleFile;
+
+fn parse(source: &str) -> Result<ParseResult, Vec<CompileError>> {
+    parse_for(source, SbpfVersion::V0)
+}
+
+fn parse_for(source: &str, version: SbpfVersion) -> Result<ParseResult, Vec<CompileError>> {
+    let file = SimpleFile::new("test.s".to_string(), source.to_string());
+    parse_source(&file, version)
+}
+
+fn text_bytes(source: &str) -> Vec<u8> {
+    text_bytes_for(source, SbpfVersion::V0)
+}
+
+fn text_bytes_for(source: &str, version: SbpfVersion) -> Vec<u8> {
+    parse_for(source, version).expect("parse failed").code_section.bytecode()
+}
+
+#[test]
+fn test_call_internal_function_is_pc_relative() {
+    let bytes = text_bytes(
+        ".globl entrypoint
+entrypoint:
+  call helper
+  exit
+helper:
+  mov64 r0, 1
+  exit",
+    );
+    // call: src = 1, imm = 1 (skip the exit after the call)
+    assert_eq!(&bytes[..8], &[0x85, 0x10, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn test_call_backwards_to_internal_function() {
+    let bytes = text_bytes(
+        "helper:
+  exit
+.globl entrypoint
+entrypoint:
+  lddw r1, 0
+  call helper
+  exit",
+    );
+    // call at instruction index 3 targets index 0
+    assert_eq!(&bytes[24..32], &[0x85, 0x10, 0x00, 0x00, 0xfc, 0xff, 0xff, 0xff]);
+}
+
+#[test]
+fn test_call_internal_function_needs_no_relocation() {
+    let result = parse(
+        ".globl entrypoint
+entrypoint:
+  call helper
+  exit
+helper:
+  exit",
+    )
+    .unwrap();
+    assert!(result.prog_is_static);
+    assert!(result.relocation_data.get_rel_dyns().is_empty());
+    assert!(result.dynamic_symbols.get_call_targets().is_empty());
+}
+
+#[test]
+fn test_call_syscall_is_relocated() {
+    let result = parse(
+        ".globl entrypoint
+entrypoint:
+  call sol_log_
+  exit",
+    )
+    .unwrap();
+    assert!(!result.prog_is_static);
+    assert_eq!(result.dynamic_symbols.get_call_targets(), vec![("sol_log_".to_string(), 0)]);
+    assert_eq!(&result.code_section.bytecode()[..8], &[0x85, 0x10, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+}
+
+const FUNCTIONS: &str = ".globl entrypoint
+.type helper, @function
+helper:
+  mov64 r0, 1
+  exit
+.size helper, 16
+
+.type entrypoint, @function
+entrypoint:
+  call helper
+  call sol_log_
+  exit";
+
+#[test]
+fn test_function_symbols() {
+    let result = parse(FUNCTIONS).unwrap();
+    let functions: Vec<_> = result.function_symbols.iter()
+        .map(|f| (f.name.as_str(), f.offset, f.size, f.is_global))
+        .collect();
+    assert_eq!(functions, vec![("helper", 0, 16, false), ("entrypoint", 16, 24, true)]);
+}
+
+#[test]
+fn test_function_symbols_in_elf() {
+    let program = Program::from_parse_result(parse(FUNCTIONS).unwrap());
+    // entrypoint is the third instruction after the 3 program headers
+    assert_eq!(program.elf_header.e_entry, 64 + 3 * 56 + 16);
+    let names: Vec<_> = program.sections.iter().map(|s| s.name().to_string()).collect();
+    assert_eq!(names, vec!["", ".text", ".dynamic", ".dynsym", ".dynstr", ".rel.dyn", ".symtab", ".strtab", ".shstrtab"]);
+    let strtab = program.sections.iter().find(|s| s.name() == ".strtab").unwrap();
+    assert!(strtab.bytecode().starts_with(b"\0helper\0entrypoint\0"));
+}
+
+#[test]
+fn test_function_size_ends_at_next_global() {
+    let source = ".globl entrypoint
+.type helper, @function
+helper:
+  mov64 r0, 1
+  exit
+entrypoint:
+  call helper
+  exit";
+    let result = parse(source).unwrap();
+    let functions: Vec<_> = result.function_symbols.iter().map(|f| (f.name.as_str(), f.offset, f.size)).collect();
+    assert_eq!(functions, vec![("helper", 0, 16)]);
+    let elf = elf_bytes(source, SbpfVersion::V0);
+    let disassembled = crate::disasm::disassemble(&elf).unwrap();
+    assert!(!disassembled.contains(".size"), "{}", disassembled);
+    assert_eq!(elf_bytes(&disassembled, SbpfVersion::V0), elf);
+}
+
+#[test]
+fn test_entry_point_is_first_global_or_entry() {
+    let source = ".globl process
+.globl helper
+helper:
+  exit
+process:
+  call helper
+  exit";
+    let entry = |source: &str| Program::from_parse_result(parse(source).unwrap()).elf_header.e_entry;
+    let helper = entry(&source.replace(".globl process\n.globl helper", ".globl helper\n.globl process"));
+    assert_eq!(entry(source), helper + 8);
+    // .entry wins wherever it is, and exports the label
+    assert_eq!(entry(&format!("{}\n.entry helper", source)), helper);
+    assert_eq!(entry(&source.replace(".globl helper", ".entry helper")), helper);
+    let errors = parse(&format!(".entry helper\n.entry process\n{}", source)).err().unwrap();
+    assert!(matches!(errors[..], [CompileError::InvalidGlobalDecl { .. }]));
+    assert_eq!(errors[0].label(), "the entry point is already set by an earlier .entry");
+}
+
+#[test]
+fn test_type_decl_requires_text_label() {
+    let errors = parse(".type missing, @function\nexit").err().unwrap();
+    assert!(matches!(errors[..], [CompileError::UndefinedLabel { .. }]));
+}
+
+#[test]
+fn test_callx_register_in_imm() {
+    let bytes = text_bytes(
+        ".globl entrypoint
+entrypoint:
+  callx r3
+  exit",
+    );
+    assert_eq!(&bytes[..8], &[0x8d, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn test_callx_requires_register() {
+    let errors = parse("callx handler\nexit").err().unwrap();
+    assert!(matches!(errors[..], [CompileError::InvalidInstruction { .. }]));
+}
+
+#[test]
+fn test_lddw_text_label_is_function_pointer() {
+    let result = parse(
+        ".globl entrypoint
+entrypoint:
+  lddw r1, handler
+  callx r1
+  exit
+handler:
+  exit",
+    )
+    .unwrap();
+    let bytes = result.code_section.bytecode();
+    // handler sits at text offset 32, after the elf header and 3 program headers
+    let addr = (64 + 3 * 56 + 32) as u32;
+    assert_eq!(&bytes[4..8], &addr.to_le_bytes());
+    let rel_dyns = result.relocation_data.get_rel_dyns();
+    assert_eq!(rel_dyns.len(), 1);
+    assert_eq!(rel_dyns[0].0, 0);
+}
+
+#[test]
+fn test_murmur3_syscall_hashes() {
+    assert_eq!(murmur3_32(b"sol_log_"), 0x207559bd);
+    assert_eq!(murmur3_32(b"abort"), 0xb6fc1a11);
+}
+
+#[test]
+fn test_static_syscall_on_v3() {
+    let result = parse_for(
+        ".globl entrypoint
+entrypoint:
+  call sol_log_
+  exit",
+        SbpfVersion::V3,
+    )
+    .unwrap();
+    assert!(result.prog_is_static);
+    assert!(result.relocation_data.get_rel_dyns().is_empty());
+    assert_eq!(&result.code_section.bytecode()[..8], &[0x85, 0x00, 0x00, 0x00, 0xbd, 0x59, 0x75, 0x20]);
+}
+
+#[test]
+fn test_callx_register_in_src_on_v2() {
+    let bytes = text_bytes_for("callx r3\nexit", SbpfVersion::V2);
+    assert_eq!(&bytes[..8], &[0x8d, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn test_memory_instruction_classes_on_v2() {
+    let source = "ldxdw r1, [r2+8]\nstxw [r1+4], r3\nexit";
+    let legacy = text_bytes_for(source, SbpfVersion::V0);
+    assert_eq!((legacy[0], legacy[8]), (0x79, 0x63));
+    let v2 = text_bytes_for(source, SbpfVersion::V2);
+    assert_eq!((v2[0], v2[8]), (0x9c, 0x8f));
+    assert_eq!(&legacy[1..8], &v2[1..8]);
+}
+
+#[test]
+fn test_sub_immediate_keeps_its_meaning_on_v2() {
+    let source = "sub64 r1, 5\nsub32 r2, 0xffffffff\nsub64 r3, r4\nexit";
+    let legacy = text_bytes_for(source, SbpfVersion::V0);
+    assert_eq!(&legacy[..8], &[0x17, 0x01, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00]);
+    // v2 computes `imm - dst`, the immediate is negated and added instead
+    let v2 = text_bytes_for(source, SbpfVersion::V2);
+    assert_eq!(&v2[..16], &[0x07, 0x01, 0x00, 0x00, 0xfb, 0xff, 0xff, 0xff, 0x04, 0x02, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    assert_eq!(&v2[16..24], &legacy[16..24]);
+    let errors = parse_for("sub64 r1, -0x80000000\nexit", SbpfVersion::V2).err().unwrap();
+    assert!(matches!(errors[..], [CompileError::ImmediateOutOfRange { .. }]));
+    assert!(parse_for("sub64 r1, -0x80000000\nexit", SbpfVersion::V0).is_ok());
+
+    // a sub of an immediate from another toolchain has no source form
+    let mut elf = elf_bytes(".globl entrypoint\nentrypoint:\n  sub64 r1, 5\n  exit", SbpfVersion::V2);
+    let add = elf.windows(8).position(|slot| slot == [0x07, 0x01, 0x00, 0x00, 0xfb, 0xff, 0xff, 0xff]).unwrap();
+    elf[add] = 0x17;
+    let disassembled = crate::disasm::disassemble(&elf).unwrap();
+    assert!(disassembled.contains(".8byte 0xfffffffb00000117 # r1 = -5 - r1"), "{}", disassembled);
+}
+
+#[test]
+fn test_opcode_unsupported_on_target() {
+    let errors = parse_for("mul64 r1, 2\nexit", SbpfVersion::V2).err().unwrap();
+    assert!(matches!(&errors[..], [CompileError::UnsupportedOpcode { opcode, version, .. }] if opcode == "mul64" && version == "v2"));
+    let errors = parse_for("lmul64 r1, 2\nexit", SbpfVersion::V0).err().unwrap();
+    assert!(matches!(errors[..], [CompileError::UnsupportedOpcode { .. }]));
+    assert!(parse_for("lmul64 r1, 2\nhor64 r1, 1\nexit", SbpfVersion::V2).is_ok());
+}
+
+#[test]
+fn test_undefined_jump_and_call_targets() {
+    let source = ".extern my_syscall
+entrypoint:
+  jeq r1, 0, nowhere
+  ja missing
+  call sol_log
+  call data
+  call my_syscall
+  call sol_log_
+  exit
+.rodata
+  data: .byte 1";
+    let errors = parse(source).err().unwrap();
+    let undefined: Vec<_> = errors.iter()
+        .map(|e| match e {
+            CompileError::UndefinedLabel { label, .. } => label.as_str(),
+            e => panic!("unexpected error {:?}", e),
+        })
+        .collect();
+    assert_eq!(undefined, vec!["nowhere", "missing", "sol_log", "data"]);
+}
+
+#[test]
+fn test_branch_out_of_range() {
+    let far = format!("ja far\n{}far:\nexit", "mov64 r0, 0\n".repeat(32767));
+    assert!(parse(&far).is_ok());
+    let too_far = format!("ja far\n{}far:\nexit", "mov64 r0, 0\n".repeat(32768));
+    let errors = parse(&too_far).err().unwrap();
+    assert!(matches!(&errors[..], [CompileError::BranchOutOfRange { offset: 32768, .. }]));
+    let errors = parse("ja +40000\nexit").err().unwrap();
+    assert!(matches!(&errors[..], [CompileError::BranchOutOfRange { offset: 40000, .. }]));
+    assert!(parse("ja -32768\nexit").is_ok());
+}
+
+#[test]
+fn test_operand_range_errors() {
+    let source = "entrypoint:
+  mov64 r1, 0x1_0000_0000
+  add64 r1, 0xFFFFFFFF
+  mov32 r1, 0x1_0000_0000
+  ldxdw r1, [r2 + 70000]
+  stxw [r1 - 32769], r2
+  lsh64 r1, 64
+  arsh32 r1, 32
+  jeq r1, 0x80000000, entrypoint
+  exit";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Value 4294967296 is out of range".to_string(), "0x1_0000_0000"),
+        ("Value 4294967295 is out of range".to_string(), "0xFFFFFFFF"),
+        ("Value 4294967296 is out of range".to_string(), "0x1_0000_0000"),
+        ("Value 70000 is out of range".to_string(), "+ 70000"),
+        ("Value -32769 is out of range".to_string(), "- 32769"),
+        ("Value 64 is out of range".to_string(), "64"),
+        ("Value 32 is out of range".to_string(), "32"),
+        ("Value 2147483648 is out of range".to_string(), "0x80000000"),
+    ]);
+    assert!(errors[0].label().contains("lddw"));
+}
+
+#[test]
+fn test_operand_range_limits() {
+    assert!(parse("mov64 r1, -2147483648
+mov64 r1, 2147483647
+mov32 r1, 0xFFFFFFFF
+ldxb r1, [r2 - 32768]
+stxb [r2 + 32767], r1
+lsh64 r1, 63
+lsh32 r1, 31
+exit").is_ok());
+    assert!(parse_for("hor64 r1, 0xFFFFFFFF\nexit", SbpfVersion::V2).is_ok());
+}
+
+#[test]
+fn test_register_errors() {
+    let source = "entrypoint:
+  mov64 r11, 1
+  ldxdw r1, [r12 + 0]
+  mov64 r10, 0
+  ldxdw r10, [r1 + 0]
+  add64 r10, -64
+  stxdw [r10 - 8], r1
+  exit";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Invalid register 'r11'".to_string(), "r11"),
+        ("Invalid register 'r12'".to_string(), "r12"),
+        ("Register r10 is read-only".to_string(), "r10"),
+        ("Register r10 is read-only".to_string(), "r10"),
+        ("Register r10 is read-only".to_string(), "r10"),
+    ]);
+    assert_eq!(errors[2].span(), &(source.find("r10, 0").unwrap()..source.find("r10, 0").unwrap() + 3));
+}
+
+#[test]
+fn test_r10_adjusted_with_dynamic_stack_frames() {
+    let source = "add64 r10, -64\nstxdw [r10 - 8], r1\nldxdw r1, [r10 - 8]\nexit";
+    assert!(parse_for(source, SbpfVersion::V1).is_ok());
+    let errors = parse_for("mov64 r10, r1\nexit", SbpfVersion::V1).err().unwrap();
+    assert!(errors[0].label().contains("add64 r10"));
+}
+
+#[test]
+fn test_duplicate_definitions() {
+    let source = ".equ SIZE, 8
+entrypoint:
+loop:
+  ja loop
+loop:
+  exit
+.equ SIZE, 16
+.equ entrypoint, 0
+SIZE:
+.rodata
+msg: .ascii \"hi\"
+msg: .ascii \"ho\"";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter()
+        .map(|e| (e.to_string(), &source[e.span().clone()], &source[e.original().unwrap().clone()]))
+        .collect();
+    assert_eq!(reported, vec![
+        ("Label 'loop' is defined more than once".to_string(), "loop:", "loop:"),
+        ("Constant 'SIZE' is defined more than once".to_string(), "SIZE", "SIZE"),
+        ("Constant 'entrypoint' is defined more than once".to_string(), "entrypoint", "entrypoint:"),
+        ("Label 'SIZE' is defined more than once".to_string(), "SIZE:", "SIZE"),
+        ("Label 'msg' is defined more than once".to_string(), "msg:", "msg:"),
+    ]);
+    assert_eq!(errors[0].original().unwrap().start, source.find("loop:").unwrap());
+    assert_eq!(errors[2].label(), "already defined as a label");
+    assert_eq!(errors[3].label(), "already defined as a constant");
+}
+
+#[test]
+fn test_one_error_per_bad_line() {
+    let source = "entrypoint:
+  mov64 r1, , 2, r3
+  ldxdw r1 [r2 + 0] r4
+  mov64 r1, 1 exit
+  mvo64 r1, 1
+  .bogus 1, 2, 3
+  exit";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Invalid 'mov64' instruction".to_string(), "mov64"),
+        ("Invalid 'ldxdw' instruction".to_string(), "ldxdw"),
+        ("Expected end of line".to_string(), "exit"),
+        ("Unknown instruction 'mvo64'".to_string(), "mvo64"),
+        ("Invalid directive 'bogus'".to_string(), ".bogus"),
+    ]);
+    assert_eq!(errors[2].label(), "expects one instruction per line");
+}
+
+#[test]
+fn test_statements_after_labels_and_section() {
+    let source = ".globl entrypoint
+entrypoint: mov64 r0, 0
+  exit r1, r2
+.section .rodata
+msg: .ascii \"hi\"";
+    let errors = parse(source).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(&source[errors[0].span().clone()], "r1, r2");
+    assert!(parse(&source.replace("exit r1, r2", "exit")).is_ok());
+}
+
+#[test]
+fn test_lexer_and_parser_errors_together() {
+    let source = "entrypoint:
+  mov64 r1, 0b12
+  mov64 r2, , 3
+  ldxdw r3, [r1 + $]
+  exit exit";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Invalid number '0b12'".to_string(), "0b12"),
+        ("Invalid 'mov64' instruction".to_string(), "mov64"),
+        ("Unexpected character '$'".to_string(), "$"),
+        ("Expected end of line".to_string(), "exit"),
+    ]);
+    // labels on lines that failed to lex aren't reported as undefined
+    assert_eq!(parse("ja ok
+ok: mov64 r1, 0x
+exit").err().unwrap().len(), 1);
+}
+
+#[test]
+fn test_lints() {
+    let source = ".globl entrypoint
+.extern sol_log_ unused_syscall
+.equ UNUSED, 1
+.equ USED, 2
+entrypoint:
+  mov64 r0, USED
+  call sol_log_
+  exit
+  mov64 r0, 1
+  mov64 r0, 2
+helper:
+  mov64 r0, 3";
+    let warnings = parse(source).unwrap().warnings;
+    let reported: Vec<_> = warnings.iter().map(|w| (w.lint(), &source[w.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("unused_externs", "unused_syscall"),
+        ("unused_constants", "UNUSED"),
+        ("unreachable_code", "mov64"),
+        ("unused_labels", "helper:"),
+        ("missing_exit", "mov64"),
+    ]);
+    assert_eq!(warnings[2].span().start, source.find("mov64 r0, 1").unwrap());
+    assert!(parse("entrypoint:
+  ja entrypoint
+.globl entrypoint").unwrap().warnings.is_empty());
+}
+
+#[test]
+fn test_lint_policy() {
+    use crate::warnings::{LintLevel, LintPolicy};
+    let mut lints = LintPolicy::default();
+    assert_eq!(lints.level("unused_labels"), LintLevel::Warn);
+    lints.set("warnings", LintLevel::Deny).unwrap();
+    lints.set("unused-labels", LintLevel::Allow).unwrap();
+    assert_eq!(lints.level("unused_labels"), LintLevel::Allow);
+    assert_eq!(lints.level("missing_exit"), LintLevel::Deny);
+    assert!(lints.set("unused_lables", LintLevel::Warn).is_err());
+    // -D warnings -W unused_labels
+    lints.set("unused_labels", LintLevel::Warn).unwrap();
+    assert_eq!(lints.level("unused_labels"), LintLevel::Warn);
+    // -D unused_labels -A unused_labels
+    lints.set("unused_labels", LintLevel::Deny).unwrap();
+    lints.set("unused_labels", LintLevel::Allow).unwrap();
+    assert_eq!(lints.level("unused_labels"), LintLevel::Allow);
+}
+
+#[test]
+fn test_suggestions() {
+    use crate::errors::AsDiagnostic;
+    use crate::suggest::suggest;
+    assert_eq!(suggest("ldxwd", crate::opcode::Opcode::mnemonics()), Some("ldxdw"));
+    assert_eq!(suggest("MOV64", crate::opcode::Opcode::mnemonics()), None);
+    assert_eq!(suggest("frobnicate", crate::opcode::Opcode::mnemonics()), None);
+
+    let source = ".extrn sol_log_
+.equ COUNT, 3
+entrypoint:
+  ldxwd r1, [r2 + 0]
+  mov64 r1, COUNTT
+  jeq r1, 0, entrypont
+  exit";
+    let errors = parse(source).err().unwrap();
+    let suggestions: Vec<_> = errors.iter().map(|e| e.suggestion()).collect();
+    assert_eq!(suggestions, vec![Some(".extern".to_string()), Some("ldxdw".to_string())]);
+    assert!(errors[1].to_diagnostic().notes.contains(&"expected `ldxdw <register>, [<register> + <offset>]`".to_string()));
+
+    let errors = parse(&source[source.find(".equ").unwrap()..].replace("ldxwd", "ldxdw")).err().unwrap();
+    let suggestions: Vec<_> = errors.iter().map(|e| e.suggestion()).collect();
+    assert_eq!(suggestions, vec![Some("COUNT".to_string()), Some("entrypoint".to_string())]);
+    let errors = parse("call sol_log\nexit").err().unwrap();
+    assert_eq!(errors[0].to_diagnostic().notes, vec!["help: did you mean `sol_log_`?".to_string()]);
+}
+
+#[test]
+fn test_invalid_instruction_lists_operand_forms() {
+    use crate::errors::AsDiagnostic;
+    let errors = parse("mov64 r1\nexit").err().unwrap();
+    assert_eq!(errors[0].to_diagnostic().notes,
+        vec!["expected `mov64 <register>, <immediate value>` or `mov64 <register>, <register>`".to_string()]);
+}
+
+#[test]
+fn test_json_diagnostics() {
+    use crate::errors::AsDiagnostic;
+    use crate::message_format::to_json;
+    let source = "entrypoint:\n  ja loop\nloop:\nloop:\n  exit";
+    let file = SimpleFile::new("test.s".to_string(), source.to_string());
+    let errors = parse(source).err().unwrap();
+    let json = to_json(&errors[0].to_diagnostic(), &file);
+    assert_eq!((&json["severity"], &json["code"]), (&"error".into(), &"E0021".into()));
+    assert_eq!(json["message"], "Label 'loop' is defined more than once");
+    assert_eq!(json["file"], "test.s");
+    let labels = json["labels"].as_array().unwrap();
+    assert_eq!(labels.len(), 2);
+    assert_eq!((&labels[0]["primary"], &labels[0]["line_start"], &labels[0]["column_start"]), (&true.into(), &4.into(), &1.into()));
+    assert_eq!((&labels[1]["primary"], &labels[1]["byte_start"], &labels[1]["message"]), (&false.into(), &22.into(), &"first defined here".into()));
+    assert!(json["rendered"].as_str().unwrap().starts_with("error[E0021]: Label 'loop'"));
+
+    let warnings = parse(".globl entrypoint\nentrypoint:\n  mov64 r0, 0").unwrap().warnings;
+    let json = to_json(&warnings[0].to_diagnostic(crate::warnings::LintLevel::Warn), &file);
+    assert_eq!((&json["severity"], &json["code"]), (&"warning".into(), &"W0005".into()));
+}
+
+#[test]
+fn test_neg_encodes_register() {
+    assert_eq!(&text_bytes("neg64 r4\nexit")[..8], &[0x87, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn test_byte_swaps_take_a_width() {
+    let bytes = text_bytes("le r1, 16\nbe r2, 64\nexit");
+    assert_eq!(&bytes[..16], &[0xd4, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0xdc, 0x02, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00]);
+    let errors = parse("be r1\nexit").err().unwrap();
+    assert!(matches!(errors[..], [CompileError::InvalidInstruction { .. }]));
+    let errors = parse("le r1, 8\nexit").err().unwrap();
+    assert!(matches!(&errors[..], [CompileError::ImmediateOutOfRange { value: 8, .. }]));
+    assert_eq!(errors[0].label(), "expects a width of 16, 32 or 64");
+    let errors = parse_for("le r1, 16\nexit", SbpfVersion::V2).err().unwrap();
+    assert!(matches!(errors[..], [CompileError::UnsupportedOpcode { .. }]));
+}
+
+#[test]
+fn test_e_flags_follow_version() {
+    for version in [SbpfVersion::V0, SbpfVersion::V1, SbpfVersion::V2, SbpfVersion::V3] {
+        let program = Program::from_parse_result(parse_for(FUNCTIONS, version).unwrap());
+        assert_eq!(program.elf_header.e_flags, version as u32);
+    }
+}
+
+const HELLO: &str = ".globl entrypoint
+entrypoint:
+  lddw r1, message
+  lddw r2, MSG_LEN
+  call sol_log_
+  exit
+.rodata
+  message: .ascii \"Hello, Solana!\"
+  .equ MSG_LEN, . - message";
+
+#[test]
+fn test_v3_static_layout() {
+    let program = Program::from_parse_result(parse_for(HELLO, SbpfVersion::V3).unwrap());
+    assert_eq!(program.elf_header.e_machine, 263);
+    assert_eq!(program.elf_header.e_entry, 0);
+    let segments: Vec<_> = program.program_headers.iter()
+        .map(|ph| (ph.p_type, ph.p_flags, ph.p_offset, ph.p_vaddr, ph.p_filesz))
+        .collect();
+    assert_eq!(segments, vec![(1, 1, 64 + 2 * 56, 0, 48), (1, 4, 64 + 2 * 56 + 48, 0x1_0000_0000, 14)]);
+    let names: Vec<_> = program.sections.iter().map(|s| s.name().to_string()).collect();
+    assert_eq!(names, vec!["", ".text", ".rodata", ".shstrtab"]);
+}
+
+#[test]
+fn test_v3_lddw_resolves_fixed_addresses() {
+    let result = parse_for(HELLO, SbpfVersion::V3).unwrap();
+    assert!(result.relocation_data.get_rel_dyns().is_empty());
+    let bytes = result.code_section.bytecode();
+    // message is the first byte of .rodata
+    assert_eq!(&bytes[4..8], &[0, 0, 0, 0]);
+    assert_eq!(&bytes[12..16], &[1, 0, 0, 0]);
+}
+
+const TABLE: &str = ".globl entrypoint
+entrypoint:
+  lddw r1, table + 16
+  lddw r2, 0x10 + table
+  lddw r3, (table + 24) - ENTRY
+  exit
+.rodata
+  table: .quad 1, 2, 3, 4
+.equ ENTRY, 8";
+
+#[test]
+fn test_lddw_label_offset_is_relocated() {
+    let result = parse(TABLE).unwrap();
+    let mut offsets: Vec<_> = result.relocation_data.get_rel_dyns().iter().map(|(offset, _, _)| *offset).collect();
+    offsets.sort();
+    assert_eq!(offsets, vec![0, 16, 32]);
+    // table follows the 56 bytes of .text, after the elf header and 3 program headers
+    let addr = (64 + 3 * 56 + 56 + 16) as u32;
+    let bytes = result.code_section.bytecode();
+    for lddw in bytes.chunks(16).take(3) {
+        assert_eq!(&lddw[4..8], &addr.to_le_bytes());
+    }
+}
+
+#[test]
+fn test_v3_lddw_label_offset_is_absolute() {
+    let result = parse_for(TABLE, SbpfVersion::V3).unwrap();
+    assert!(result.relocation_data.get_rel_dyns().is_empty());
+    let bytes = result.code_section.bytecode();
+    for lddw in bytes.chunks(16).take(3) {
+        assert_eq!(&lddw[4..8], &[16, 0, 0, 0]);
+        assert_eq!(&lddw[12..16], &[1, 0, 0, 0]);
+    }
+}
+
+#[test]
+fn test_lddw_label_offset_errors() {
+    let source = "lddw r1, table * 2
+lddw r2, table + table
+lddw r3, -table
+exit
+.rodata
+  table: .quad 1";
+    let errors = parse(source).err().unwrap();
+    let spans: Vec<_> = errors.iter().map(|e| &source[e.span().clone()]).collect();
+    assert_eq!(spans, vec!["table * 2", "table + table", "-table"]);
+    assert!(errors.iter().all(|e| matches!(e, CompileError::InvalidAddressExpression { .. })));
+}
+
+#[test]
+fn test_v3_function_symbols_use_vaddr() {
+    let program = Program::from_parse_result(parse_for(FUNCTIONS, SbpfVersion::V3).unwrap());
+    assert_eq!(program.elf_header.e_entry, 16);
+    let names: Vec<_> = program.sections.iter().map(|s| s.name().to_string()).collect();
+    assert_eq!(names, vec!["", ".text", ".symtab", ".strtab", ".shstrtab"]);
+}
+
+fn rodata_bytes(source: &str) -> Vec<u8> {
+    let result = parse(source).expect("parse failed");
+    result.data_section.get_nodes().iter()
+        .flat_map(|node| match node {
+            ASTNode::ROData { rodata, .. } => rodata.bytecode(),
+            ASTNode::Align { align, .. } => align.bytecode(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_rodata_integer_directives() {
+    let bytes = rodata_bytes(
+        ".equ SEED, 7
+exit
+.rodata
+  table: .byte 1, 0xff, -1, SEED + 1
+  .2byte 0x1234
+  .short -2
+  .4byte 1
+  .word 2
+  .long 3
+  .8byte 0x0102030405060708
+  .quad 9",
+    );
+    assert_eq!(bytes, [
+        &[1, 0xff, 0xff, 8][..],
+        &[0x34, 0x12, 0xfe, 0xff],
+        &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0],
+        &[8, 7, 6, 5, 4, 3, 2, 1, 9, 0, 0, 0, 0, 0, 0, 0],
+    ].concat());
+}
+
+#[test]
+fn test_rodata_string_and_fill_directives() {
+    let result = parse(
+        "exit
+.rodata
+  a: .asciz \"hi\", \"yo\"
+  b: .zero 3
+  c: .space 2, 0xaa
+  d: .fill 2, 2, 0x0102
+  e: .ascii \"!\"",
+    )
+    .unwrap();
+    let bytes = result.data_section.bytecode();
+    assert_eq!(&bytes[..], b"hi\0yo\0\0\0\0\xaa\xaa\x02\x01\x02\x01!");
+    assert_eq!(result.data_section.get_size(), 16);
+}
+
+#[test]
+fn test_rodata_labels_follow_data_sizes() {
+    let result = parse(
+        ".globl entrypoint
+entrypoint:
+  lddw r1, second
+  exit
+.rodata
+  first: .byte 1, 2, 3
+  second: .quad 4",
+    )
+    .unwrap();
+    // second starts 3 bytes into .rodata, which follows the 24 bytes of .text
+    let addr = (64 + 3 * 56 + 24 + 3) as u32;
+    assert_eq!(&result.code_section.bytecode()[4..8], &addr.to_le_bytes());
+}
+
+#[test]
+fn test_rodata_function_pointer_table() {
+    let source = ".globl entrypoint
+entrypoint:
+  exit
+handler:
+  exit
+.rodata
+  handlers:
+    .quad handler, entrypoint";
+    let result = parse_for(source, SbpfVersion::V1).unwrap();
+    let rel_dyns = result.relocation_data.get_rel_dyns();
+    let mut offsets: Vec<_> = rel_dyns.iter().map(|(offset, _, _)| *offset).collect();
+    offsets.sort();
+    assert_eq!(offsets, vec![16, 24]);
+    let text = (64 + 3 * 56) as u64;
+    assert_eq!(result.data_section.bytecode(), [(text + 8).to_le_bytes(), text.to_le_bytes()].concat());
+
+    // v0 loaders read the address from the upper half
+    let result = parse_for(source, SbpfVersion::V0).unwrap();
+    assert_eq!(&result.data_section.bytecode()[..8], &((text + 8) << 32).to_le_bytes());
+
+    // v3 addresses are fixed, nothing to relocate
+    let result = parse_for(source, SbpfVersion::V3).unwrap();
+    assert!(result.relocation_data.get_rel_dyns().is_empty());
+    assert_eq!(&result.data_section.bytecode()[..8], &8u64.to_le_bytes());
+}
+
+#[test]
+fn test_rodata_directive_errors() {
+    let source = "exit
+.rodata
+  a: .byte 256
+  b: .short 1,
+  c: .ascii 1
+  d: .fill 1, 9
+  e: .quad missing";
+    let errors = parse(source).err().unwrap();
+    let labels: Vec<_> = errors.iter().map(|e| e.label().to_string()).collect();
+    assert_eq!(labels, vec![
+        "value does not fit in .byte",
+        "expects comma separated values",
+        "expects <string literal>, ...",
+        "expects a <size> between 1 and 8",
+    ]);
+    let errors = parse("exit\n.rodata\n  e: .quad missing").err().unwrap();
+    assert!(matches!(&errors[..], [CompileError::UndefinedLabel { label, .. }] if label == "missing"));
+    let errors = parse(".byte 1\nexit").err().unwrap();
+    assert_eq!(errors[0].label(), "Data directives belong in .rodata");
+}
+
+#[test]
+fn test_rodata_alignment_after_odd_length_string() {
+    let bytes = rodata_bytes("exit
+.rodata
+  msg: .ascii \"odd\"
+  .balign 8
+  value: .quad 42
+  .p2align 4, 255
+  .byte 1");
+    let mut expected = b"odd".to_vec();
+    expected.extend([0; 5]);
+    expected.extend(42u64.to_le_bytes());
+    expected.push(1);
+    assert_eq!(bytes, expected);
+}
+
+#[test]
+fn test_rodata_alignment_moves_section_start() {
+    let source = "lddw r1, value
+exit
+exit
+.rodata
+  msg: .ascii \"odd\"
+  .align 16
+  value: .quad 42";
+    let result = parse(source).unwrap();
+    assert_eq!(result.data_section.get_align(), 16);
+    // .text starts at 232 and ends at 264, .rodata moves up to 272
+    let bytes = result.code_section.bytecode();
+    assert_eq!(&bytes[4..8], &(272u32 + 16).to_le_bytes());
+
+    let elf = Program::from_parse_result(result).emit_bytecode();
+    assert_eq!(&elf[264..272], &[0; 8]);
+    assert_eq!(&elf[272..275], b"odd");
+    assert_eq!(&elf[288..296], &42u64.to_le_bytes());
+}
+
+#[test]
+fn test_text_alignment_pads_with_ja() {
+    let result = parse("ja target
+.p2align 5
+target:
+  exit").unwrap();
+    assert_eq!(result.code_section.get_align(), 32);
+    let bytes = result.code_section.bytecode();
+    assert_eq!(&bytes[..8], &[0x05, 0, 0x03, 0, 0, 0, 0, 0]);
+    for nop in bytes[8..32].chunks(8) {
+        assert_eq!(nop, &[0x05, 0, 0, 0, 0, 0, 0, 0]);
+    }
+    assert_eq!(&bytes[32..], &[0x95, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_alignment_directive_errors() {
+    let source = "exit
+  .balign 16, 1
+  .balign 3
+  .p2align 13
+.rodata
+  .align 8, 256";
+    let errors = parse(source).err().unwrap();
+    let labels: Vec<_> = errors.iter().map(|e| e.label().to_string()).collect();
+    assert_eq!(labels, vec![
+        ".text is padded with `ja +0`, a fill value is only allowed in .rodata",
+        "expects a power of two alignment up to 4096",
+        "expects a power of two alignment up to 4096",
+        "expects <alignment>[, <fill>]",
+    ]);
+}
+
+#[test]
+fn test_string_escape_sequences() {
+    let bytes = rodata_bytes(r#"exit
+.rodata
+  msg: .ascii "a\n\t\r\0\\\"\'\x41\xff\x7""#);
+    assert_eq!(bytes, b"a\n\t\r\0\\\"'A\xff\x07");
+}
+
+#[test]
+fn test_char_literals_are_immediates() {
+    let bytes = text_bytes(r"mov64 r1, 'A'
+mov64 r2, '\n'
+mov64 r3, '\''
+exit");
+    assert_eq!(&bytes[4..8], &65i32.to_le_bytes());
+    assert_eq!(&bytes[12..16], &10i32.to_le_bytes());
+    assert_eq!(&bytes[20..24], &39i32.to_le_bytes());
+}
+
+#[test]
+fn test_string_and_char_literal_errors() {
+    let source = ".rodata\n  a: .ascii \"abc\n  b: .ascii \"\\q\"\n  c: .byte 'AB'";
+    let (_, errors) = tokenize(source);
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Unterminated string literal".to_string(), "\"abc"),
+        ("Invalid escape sequence '\\q'".to_string(), "\\q"),
+        ("Invalid character literal".to_string(), "'AB'"),
+    ]);
+}
+
+fn immediates(source: &str) -> Vec<ImmediateValue> {
+    tokenize(source).0.into_iter()
+        .filter_map(|token| match token {
+            Token::ImmediateValue(value, _) => Some(value),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_numeric_literal_forms() {
+    assert_eq!(immediates("0XFF 0xAb 0b1010 0B11 0o17 1_000_000 0x_ff_ff"), vec![
+        ImmediateValue::Addr(0xff),
+        ImmediateValue::Addr(0xab),
+        ImmediateValue::Int(10),
+        ImmediateValue::Int(3),
+        ImmediateValue::Int(15),
+        ImmediateValue::Int(1_000_000),
+        ImmediateValue::Addr(0xffff),
+    ]);
+    // past i64::MAX the literal is kept unsigned
+    assert_eq!(immediates("0xFFFFFFFFFFFFFFFF 9223372036854775808"), vec![
+        ImmediateValue::UInt(u64::MAX),
+        ImmediateValue::UInt(1 << 63),
+    ]);
+}
+
+#[test]
+fn test_lddw_accepts_full_u64() {
+    let bytes = text_bytes("lddw r1, 0xFFFFFFFFFFFFFFFF\nexit");
+    assert_eq!(&bytes[..16], &[0x18, 0x01, 0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    let bytes = rodata_bytes("exit\n.rodata\n  max: .quad 0xFFFFFFFFFFFFFFFF\n  .p2align 4, 0xAA");
+    assert_eq!(bytes, [[0xff; 8], [0xaa; 8]].concat());
+}
+
+#[test]
+fn test_invalid_numeric_literals() {
+    let source = "0x 0b102 0o8 18446744073709551616 1abc";
+    let (_, errors) = tokenize(source);
+    let reported: Vec<_> = errors.iter().map(|e| (&source[e.span().clone()], e.label())).collect();
+    assert_eq!(reported, vec![
+        ("0x", "expects hexadecimal digits"),
+        ("0b102", "expects binary digits"),
+        ("0o8", "expects octal digits"),
+        ("18446744073709551616", "does not fit in 64 bits"),
+        ("1abc", "expects decimal digits"),
+    ]);
+    let errors = parse("exit\n.rodata\n  a: .byte 0x100\n  b: .word 0xFFFFFFFFFFFFFFFF").err().unwrap();
+    let labels: Vec<_> = errors.iter().map(|e| e.label().to_string()).collect();
+    assert_eq!(labels, vec!["value does not fit in .byte", "value does not fit in .word"]);
+}
+
+fn lddw_imm(expr: &str) -> i64 {
+    let bytes = text_bytes(&format!("lddw r1, {}\nexit", expr));
+    i64::from_le_bytes([&bytes[4..8], &bytes[12..16]].concat().try_into().unwrap())
+}
+
+#[test]
+fn test_constant_expression_precedence() {
+    assert_eq!(lddw_imm("1 + 2 * 3"), 7);
+    assert_eq!(lddw_imm("(1 + 2) * 3"), 9);
+    assert_eq!(lddw_imm("1 << 4 | 1"), 17);
+    assert_eq!(lddw_imm("~0 & 0xFF"), 0xff);
+    assert_eq!(lddw_imm("0xFF ^ 0x0F"), 0xf0);
+    assert_eq!(lddw_imm("-8 / 3"), -2);
+    assert_eq!(lddw_imm("17 % 5 - -1"), 3);
+    assert_eq!(lddw_imm("10 - 4 - 3"), 3);
+    assert_eq!(lddw_imm("0x8000000000000000 >> 63"), 1);
+    assert_eq!(lddw_imm("~0xFFFFFFFFFFFFFFFF"), 0);
+    assert_eq!(lddw_imm("1 << 63"), i64::MIN);
+}
+
+#[test]
+fn test_equ_expressions_in_operands() {
+    let bytes = text_bytes(".equ HEADER, 16
+.equ ACCOUNT_DATA, HEADER + 8*32
+ldxdw r2, [r1 + (ACCOUNT_DATA + 8)]
+ldxdw r3, [r1 - 8]
+stxdw [r10 - ACCOUNT_DATA / 2], r3
+mov64 r4, -1
+exit");
+    assert_eq!(&bytes[..8], &[0x79, 0x12, 0x18, 0x01, 0, 0, 0, 0]);
+    assert_eq!(&bytes[8..16], &[0x79, 0x13, 0xf8, 0xff, 0, 0, 0, 0]);
+    assert_eq!(&bytes[16..24], &[0x7b, 0x3a, 0x78, 0xff, 0, 0, 0, 0]);
+    assert_eq!(&bytes[24..32], &[0xb7, 0x04, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+}
+
+#[test]
+fn test_memory_offset_sign_covers_the_whole_expression() {
+    let bytes = text_bytes(".equ X, 10
+start:
+ldxdw r2, [r1 - 5 & 3]
+ldxdw r2, [r1 - 8 | 1]
+stxdw [r10 - 8 | 1], r2
+ldxdw r2, [r1 - X % 3]
+stxdw [r10 + 2 * 4], r2
+ldxdw r2, [r1 - (end - start)]
+end:
+exit");
+    let offsets: Vec<_> = bytes.chunks(8).take(6).map(|slot| i16::from_le_bytes([slot[2], slot[3]])).collect();
+    assert_eq!(offsets, vec![-1, -9, -9, -1, 8, -48]);
+}
+
+#[test]
+fn test_constant_expression_errors() {
+    let source = ".equ ZERO, 0
+mov64 r1, 4 / ZERO
+mov64 r1, 8 % (ZERO * 2)
+lddw r1, 0xFFFFFFFFFFFFFFFF + 1
+mov64 r1, 1 << 64
+mov64 r1, (1 + 2
+exit";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Division by zero in constant expression".to_string(), "ZERO"),
+        ("Division by zero in constant expression".to_string(), "ZERO * 2"),
+        ("Constant expression overflows 64 bits".to_string(), "0xFFFFFFFFFFFFFFFF + 1"),
+        ("Constant expression overflows 64 bits".to_string(), "64"),
+        ("Invalid expression".to_string(), "("),
+    ]);
+}
+
+#[test]
+fn test_label_differences() {
+    let source = ".globl entrypoint
+entrypoint:
+  lddw r1, message
+  lddw r2, MSG_LEN
+  mov64 r3, end - entrypoint
+  jeq r1, . - entrypoint, end
+end:
+  exit
+.rodata
+  message: .ascii \"Hello, Solana!\"
+  .equ MSG_LEN, . - message
+  len: .quad . - message
+  .byte after - len, TOTAL
+  after: .byte 0
+.equ TOTAL, after - entrypoint";
+    let bytes = text_bytes(source);
+    assert_eq!(&bytes[16..24], &[0x18, 0x02, 0, 0, 14, 0, 0, 0]);
+    assert_eq!(&bytes[32..40], &[0xb7, 0x03, 0, 0, 48, 0, 0, 0]);
+    assert_eq!(&bytes[40..48], &[0x15, 0x01, 0, 0, 40, 0, 0, 0]);
+    // .rodata follows the 56 bytes of .text
+    let rodata = rodata_bytes(source);
+    assert_eq!(&rodata[14..], &[14, 0, 0, 0, 0, 0, 0, 0, 10, 56 + 24, 0]);
+}
+
+#[test]
+fn test_label_difference_errors() {
+    let source = "entrypoint:
+  mov64 r1, message + 1
+  mov64 r2, message * 2
+  mov64 r3, MISSING - entrypoint
+  exit
+.rodata
+  message: .byte message_end - message, 0
+  .fill 300
+  message_end:
+.equ A, B + 1
+.equ B, A";
+    let errors = parse(source).err().unwrap();
+    let reported: Vec<_> = errors.iter().map(|e| (e.to_string(), &source[e.span().clone()])).collect();
+    assert_eq!(reported, vec![
+        ("Invalid use of a label address".to_string(), "message + 1"),
+        ("Invalid use of a label address".to_string(), "message * 2"),
+        ("Undefined label 'MISSING'".to_string(), "MISSING"),
+        ("Invalid rodata declaration".to_string(), "message_end - message"),
+        ("Undefined label 'B'".to_string(), "B"),
+        ("Undefined label 'A'".to_string(), "A"),
+    ]);
+    assert_eq!(errors[4].label(), "Circular constant definition");
+    assert!(parse(".align end - start
+start:
+exit
+end:").is_err());
+}
+
+#[test]
+fn test_every_code_is_explained() {
+    use crate::errors::ERROR_CODES;
+    use crate::explain::explain;
+    use crate::warnings::WARNING_CODES;
+    for code in ERROR_CODES.iter().chain(WARNING_CODES) {
+        let explanation = explain(code).unwrap_or_else(|| panic!("{} has no explanation", code));
+        // the erroneous example, then the fixed one
+        let examples: Vec<String> = explanation.split("```sbpf\n").skip(1)
+            .map(|block| block.split("```").next().unwrap().to_string())
+            .collect();
+        if examples.is_empty() {
+            assert!(explanation.contains("```sbpf,ignore"), "{} has no examples", code);
+            continue;
+        }
+        assert_eq!(examples.len(), 2, "{}", code);
+        let codes = |source: &str| match parse(source) {
+            Ok(result) => result.warnings.iter().map(|w| w.code()).collect::<Vec<_>>(),
+            Err(errors) => errors.iter().map(|e| e.code()).collect(),
+        };
+        assert!(codes(&examples[0]).contains(code), "{} example gives {:?}", code, codes(&examples[0]));
+        assert!(parse(&examples[1]).is_ok_and(|result| result.warnings.is_empty()), "{} fix gives {:?}", code, codes(&examples[1]));
+    }
+    assert_eq!(explain("e7"), explain("E0007"));
+    assert!(explain("E0999").is_none() && explain("x").is_none());
+}
+
+#[test]
+fn test_analysis_navigation() {
+    use crate::analysis::{Analysis, SymbolKind};
+    let source = ".globl entrypoint
+.equ SIZE, 4 * 2
+entrypoint:
+  mov64 r1, SIZE
+  jeq r1, 0, done
+  lddw r2, msg
+done:
+  exit
+.rodata
+  msg: .ascii \"hi\"";
+    let analysis = Analysis::new(source, SbpfVersion::V0);
+    let at = |needle: &str, nth: usize| source.match_indices(needle).nth(nth).unwrap().0;
+    let symbols: Vec<_> = analysis.symbols().iter().map(|s| (s.name.as_str(), s.kind, s.detail.as_str())).collect();
+    assert_eq!(symbols, vec![
+        ("SIZE", SymbolKind::Constant, "= 4 * 2 = 8"),
+        ("entrypoint", SymbolKind::Label, ".text+0x0"),
+        ("done", SymbolKind::Label, ".text+0x20"),
+        ("msg", SymbolKind::Data, ".rodata+0x0"),
+    ]);
+    assert_eq!(analysis.definition(at("done", 0) + 2), Some(at("done", 1)..at("done", 1) + 4));
+    assert_eq!(analysis.definition(at("SIZE", 1)), Some(at("SIZE", 0)..at("SIZE", 0) + 4));
+    assert_eq!(analysis.references(at("done:", 0), true), vec![at("done", 0)..at("done", 0) + 4, at("done", 1)..at("done", 1) + 4]);
+    // `.globl` refers to the label
+    assert_eq!(analysis.references(at("entrypoint:", 0), false), vec![7..17]);
+    assert!(analysis.diagnostics().is_empty());
+}
+
+#[test]
+fn test_analysis_hover_and_completion() {
+    use crate::analysis::{Analysis, CompletionKind};
+    let source = ".globl entrypoint\nentrypoint:\n  mov64 r1, 5\n  call sol_log_\n  exit\n";
+    let analysis = Analysis::new(source, SbpfVersion::V0);
+    let at = |needle: &str| source.find(needle).unwrap();
+    assert_eq!(analysis.hover(at("mov64")).unwrap(),
+        "**mov64** `dst = src`\n\n    mov64 <register>, <immediate value>\n    mov64 <register>, <register>\n\nat .text+0x0, encoded as `b7 01 00 00 05 00 00 00`");
+    assert!(analysis.hover(at("r1")).unwrap().starts_with("**r1** argument 1"));
+    assert!(analysis.hover(at("sol_log_")).unwrap().starts_with("syscall"));
+
+    // the instruction being typed doesn't parse yet
+    let source = &format!("{}  ca", source);
+    let analysis = Analysis::new(source, SbpfVersion::V0);
+    assert!(analysis.hover(at("mov64")).unwrap().ends_with("<register>"));
+    assert_eq!(analysis.diagnostics().len(), 1);
+
+    let (replace, completions) = analysis.completions(source.len());
+    assert_eq!(replace, source.len() - 2..source.len());
+    assert!(completions.iter().any(|c| c.label == "call" && c.kind == CompletionKind::Instruction));
+    assert!(completions.iter().any(|c| c.label == ".globl" && c.kind == CompletionKind::Directive));
+    let (_, completions) = analysis.completions(at("sol_log_"));
+    assert!(completions.iter().any(|c| c.label == "sol_log_" && c.kind == CompletionKind::Syscall));
+    assert!(completions.iter().all(|c| c.kind != CompletionKind::Register));
+    let (_, completions) = analysis.completions(at("5"));
+    assert!(completions.iter().any(|c| c.label == "r10" && c.kind == CompletionKind::Register));
+}
+
+#[test]
+fn test_analysis_hover_on_registers() {
+    use crate::analysis::Analysis;
+    let source = "entrypoint:\n  ldxdw r1, [r10 - 8]\n  mov64 r11, 1\n  mov64 r200, 2\n  exit\n";
+    let analysis = Analysis::new(source, SbpfVersion::V0);
+    let at = |needle: &str| source.find(needle).unwrap();
+    assert_eq!(analysis.hover(at("r10")).unwrap(), "**r10** frame pointer, read-only");
+    assert_eq!(analysis.hover(at("r11")).unwrap(), "**r11** is not a register, expected r0 to r10");
+    assert_eq!(analysis.hover(at("r200")).unwrap(), "**r200** is not a register, expected r0 to r10");
+}
+
+#[test]
+fn test_format_layout() {
+    let source = "   .equ   SIZE,8 # bytes
+.globl entrypoint
+
+
+entrypoint: ldxdw r2,[r1+SIZE]   // length
+    // copy it
+  stxdw [r10-8],r2
+  mov64 r3 , -1
+    jeq r3,~0,done
+done:
+  exit
+.rodata
+msg: .ascii \"a, b\" # text
+  .align 8
+table: .byte 1,-2 , (SIZE*2)
+";
+    let formatted = crate::format::format(source).unwrap();
+    assert_eq!(formatted, ".equ SIZE, 8 # bytes
+.globl entrypoint
+
+entrypoint:
+  ldxdw r2, [r1 + SIZE] // length
+  // copy it
+  stxdw [r10 - 8], r2
+  mov64 r3, -1
+  jeq r3, ~0, done
+done:
+  exit
+.rodata
+msg:   .ascii \"a, b\" # text
+       .align 8
+table: .byte 1, -2, (SIZE * 2)
+");
+    // formatting is stable and doesn't change the program
+    assert_eq!(crate::format::format(&formatted).unwrap(), formatted);
+    assert_eq!(text_bytes(source), text_bytes(&formatted));
+}
+
+#[test]
+fn test_format_refuses_syntax_errors_only() {
+    let errors = crate::format::format("entrypoint:\n  mov64 r1 5\n  exit").err().unwrap();
+    assert!(matches!(errors[..], [CompileError::InvalidInstruction { .. }]));
+    // undefined labels are for the build to report
+    assert_eq!(crate::format::format("ja  nowhere").unwrap(), "  ja nowhere\n");
+}
+
+fn elf_bytes(source: &str, version: SbpfVersion) -> Vec<u8> {
+    Program::from_parse_result(parse_for(source, version).expect("parse failed")).emit_bytecode()
+}
+
+#[test]
+fn test_disasm_round_trip() {
+    let source = ".globl entrypoint
+.globl helper
+.type helper, @function
+.size helper, 8
+.extern my_fn
+entrypoint:
+  lddw r1, message + 2
+  lddw r2, table
+  ldxb r3, [r1 - 3]
+  stxdw [r10 - 8], r3
+  mov32 r4, -1
+  jne r4, 0, skip
+  jsgt r4, r3, skip
+  le r3, 16
+  be r4, 64
+  call helper
+  call sol_log_
+  call my_fn
+skip:
+  callx r2
+  exit
+helper:
+  mov64 r0, 7
+  exit
+.rodata
+.align 8
+table: .quad message, helper
+message: .ascii \"Hi \\\"there\\\"\\n\"
+  .byte 1, 2, 3
+  .zero 9
+";
+    for version in [SbpfVersion::V0, SbpfVersion::V1] {
+        let elf = elf_bytes(source, version);
+        let disassembled = crate::disasm::disassemble(&elf).unwrap();
+        assert_eq!(elf_bytes(&disassembled, version), elf, "{}", disassembled);
+        // the output is already formatted
+        assert_eq!(crate::format::format(&disassembled).unwrap(), disassembled);
+    }
+}
+
+#[test]
+fn test_disasm_names_and_versions() {
+    let source = ".globl entrypoint
+entrypoint:
+  lddw r1, message
+  ldxdw r2, [r1 + 0]
+  jeq r2, 0, done
+  call sol_log_64_
+done:
+  exit
+.rodata
+message: .asciz \"hello\"
+";
+    let disassembled = crate::disasm::disassemble(&elf_bytes(source, SbpfVersion::V0)).unwrap();
+    assert_eq!(disassembled, ".globl entrypoint
+
+entrypoint:
+  lddw r1, data_0
+  ldxdw r2, [r1 + 0]
+  jeq r2, 0, jump_28
+  call sol_log_64_
+jump_28:
+  exit
+
+.rodata
+data_0: .asciz \"hello\"
+");
+
+    // v2 moves the memory classes and v3 syscalls are hashes, v3 .rodata is
+    // at a fixed address without relocations
+    let source = ".globl entrypoint
+entrypoint:
+  ldxdw r2, [r1 + 8]
+  stxb [r10 - 1], r2
+  callx r2
+  call sol_log_
+  exit";
+    for version in [SbpfVersion::V2, SbpfVersion::V3] {
+        let elf = elf_bytes(source, version);
+        let disassembled = crate::disasm::disassemble(&elf).unwrap();
+        assert!(disassembled.starts_with(&format!("# SBPF {}", version)));
+        assert!(disassembled.contains("  ldxdw r2, [r1 + 8]\n  stxb [r10 - 1], r2\n  callx r2\n  call sol_log_\n"));
+        assert_eq!(elf_bytes(&disassembled, version), elf);
+    }
+    let elf = elf_bytes(&source.replace("exit", "lddw r3, message\n  exit\n.rodata\nmessage: .ascii \"hi\""), SbpfVersion::V3);
+    let disassembled = crate::disasm::disassemble(&elf).unwrap();
+    assert!(disassembled.contains("lddw r3, data_0"));
+    assert_eq!(elf_bytes(&disassembled, SbpfVersion::V3), elf);
+
+    assert!(crate::disasm::disassemble(b"not an elf").is_err());
+}
+
+#[test]
+fn test_operands_at_end_of_file() {
+    let errors = parse("entrypoint:\n  jeq r1, 1+2").err().unwrap();
+    assert!(matches!(&errors[..], [CompileError::InvalidInstruction { .. }]));
+    assert_eq!(errors[0].label(), "expects more operand");
+    // every cut of a program is an error or a program, never a panic
+    let source = ".globl entrypoint
+.equ N, 2
+entrypoint:
+  lddw r1, message + 1
+  ldxdw r2, [r1 + N * 4]
+  stxdw [r10 - 8], r2
+  add64 r2, N << 1
+  jeq r2, N + 1, done
+  jne r2, r3, done
+  ja done
+  call sol_log_
+done:
+  exit
+.rodata
+message: .ascii \"hi\"";
+    for end in 0..=source.len() {
+        let _ = parse(&source[..end]);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::opcode::Opcode;
+use std::fmt;
+use std::str::FromStr;
+
+// Target SBPF version, the feature gates follow the ones of the solana-sbpf loader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SbpfVersion {
+    // legacy format
+    #[default]
+    V0,
+    // SIMD-0166
+    V1,
+    // SIMD-0173, SIMD-0174
+    V2,
+    // SIMD-0178, SIMD-0189
+    V3,
+}
+
+impl SbpfVersion {
+    // SIMD-0166: r10 can be adjusted with add64 to grow or shrink the frame
+    pub fn dynamic_stack_frames(self) -> bool {
+        self >= SbpfVersion::V1
+    }
+
+    // SIMD-0174: product/quotient/remainder class replaces mul, div and mod
+    pub fn enable_pqr(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    pub fn disable_neg(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    // `sub dst, imm` computes `imm - dst`
+    pub fn swap_sub_reg_imm_operands(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    // 32 bit add and sub results are sign extended instead of zero extended
+    pub fn explicit_sign_extension_of_results(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    // SIMD-0173: instruction encoding improvements
+    pub fn callx_uses_src_reg(self) -> bool {
+        self >= SbpfVersion::V2
+    }
+
+    pub fn disable_lddw(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    pub fn disable_le(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    pub fn move_memory_instruction_classes(self) -> bool {
+        self == SbpfVersion::V2
+    }
+
+    // SIMD-0178: syscalls are encoded as murmur3 hashes of their name
+    pub fn static_syscalls(self) -> bool {
+        self >= SbpfVersion::V3
+    }
+
+    // SIMD-0189: fixed virtual addresses and no dynamic sections
+    pub fn enable_stricter_elf_headers(self) -> bool {
+        self >= SbpfVersion::V3
+    }
+
+    pub fn e_flags(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_e_flags(e_flags: u32) -> Option<Self> {
+        match e_flags {
+            0 => Some(SbpfVersion::V0),
+            1 => Some(SbpfVersion::V1),
+            2 => Some(SbpfVersion::V2),
+            3 => Some(SbpfVersion::V3),
+            _ => None,
+        }
+    }
+
+    pub fn supports(self, opcode: Opcode) -> bool {
+        match opcode {
+            Opcode::Lmul32 | Opcode::Lmul32Imm | Opcode::Lmul32Reg
+            | Opcode::Udiv32 | Opcode::Udiv32Imm | Opcode::Udiv32Reg
+            | Opcode::Urem32 | Opcode::Urem32Imm | Opcode::Urem32Reg
+            | Opcode::Sdiv32 | Opcode::Sdiv32Imm | Opcode::Sdiv32Reg
+            | Opcode::Srem32 | Opcode::Srem32Imm | Opcode::Srem32Reg
+            | Opcode::Lmul64 | Opcode::Lmul64Imm | Opcode::Lmul64Reg
+            | Opcode::Uhmul64 | Opcode::Uhmul64Imm | Opcode::Uhmul64Reg
+            | Opcode::Udiv64 | Opcode::Udiv64Imm | Opcode::Udiv64Reg
+            | Opcode::Urem64 | Opcode::Urem64Imm | Opcode::Urem64Reg
+            | Opcode::Shmul64 | Opcode::Shmul64Imm | Opcode::Shmul64Reg
+            | Opcode::Sdiv64 | Opcode::Sdiv64Imm | Opcode::Sdiv64Reg
+            | Opcode::Srem64 | Opcode::Srem64Imm | Opcode::Srem64Reg => self.enable_pqr(),
+            Opcode::Mul32 | Opcode::Mul32Imm | Opcode::Mul32Reg
+            | Opcode::Div32 | Opcode::Div32Imm | Opcode::Div32Reg
+            | Opcode::Mod32 | Opcode::Mod32Imm | Opcode::Mod32Reg
+            | Opcode::Mul64 | Opcode::Mul64Imm | Opcode::Mul64Reg
+            | Opcode::Div64 | Opcode::Div64Imm | Opcode::Div64Reg
+            | Opcode::Mod64 | Opcode::Mod64Imm | Opcode::Mod64Reg => !self.enable_pqr(),
+            Opcode::Neg32 | Opcode::Neg64 => !self.disable_neg(),
+            Opcode::Le => !self.disable_le(),
+            Opcode::Lddw => !self.disable_lddw(),
+            // hor64 takes over loading the upper half of 64-bit immediates
+            Opcode::Hor64Imm => self.disable_lddw(),
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for SbpfVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "v0" => Ok(SbpfVersion::V0),
+            "v1" => Ok(SbpfVersion::V1),
+            "v2" => Ok(SbpfVersion::V2),
+            "v3" => Ok(SbpfVersion::V3),
+            _ => Err(format!("unknown sbpf version '{}', expected one of v0, v1, v2, v3", s)),
+        }
+    }
+}
+
+impl fmt::Display for SbpfVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", *self as u8)
+    }
+}
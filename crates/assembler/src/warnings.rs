@@ -0,0 +1,99 @@
+use crate::define_compile_warnings;
+use std::collections::HashMap;
+use std::ops::Range;
+
+// Lints don't stop the build unless denied. Labels could be overridden the
+// same way as for errors
+define_compile_warnings! {
+    UnusedLabel {
+        code = "W0001",
+        lint = "unused_labels",
+        warning = "Label '{label}' is never used",
+        label = "Unused label",
+        fields = { label: String, span: Range<usize> }
+    },
+    UnusedConstant {
+        code = "W0002",
+        lint = "unused_constants",
+        warning = "Constant '{name}' is never used",
+        label = "Unused constant",
+        fields = { name: String, span: Range<usize> }
+    },
+    UnusedExtern {
+        code = "W0003",
+        lint = "unused_externs",
+        warning = "'{name}' is declared but never called",
+        label = "Unused extern declaration",
+        fields = { name: String, span: Range<usize> }
+    },
+    UnreachableCode {
+        code = "W0004",
+        lint = "unreachable_code",
+        warning = "Unreachable instruction",
+        label = "Never executed, the previous instruction doesn't fall through and no label follows it",
+        fields = { span: Range<usize> }
+    },
+    MissingExit {
+        code = "W0005",
+        lint = "missing_exit",
+        warning = "Execution can run past the end of .text",
+        label = "The last instruction falls through, end the program with `exit` or `ja`",
+        fields = { span: Range<usize> }
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+// Lint levels from `-A`, `-W` and `-D`, set in the order they were given so
+// the last one for a lint wins. A level given to a lint by name wins over
+// the one given to `warnings`, which applies to every other lint
+#[derive(Debug, Clone, Default)]
+pub struct LintPolicy {
+    warnings: LintLevel,
+    lints: HashMap<&'static str, LintLevel>,
+}
+
+impl LintPolicy {
+    // `-` and `_` are interchangeable in lint names
+    pub fn set(&mut self, name: &str, level: LintLevel) -> Result<(), String> {
+        let name = name.replace('-', "_");
+        if name == "warnings" {
+            self.warnings = level;
+            return Ok(());
+        }
+        match LINTS.iter().find(|lint| **lint == name) {
+            Some(lint) => {
+                self.lints.insert(lint, level);
+                Ok(())
+            }
+            None => Err(format!("unknown lint '{}', expected `warnings` or one of {}", name, LINTS.join(", "))),
+        }
+    }
+
+    pub fn level(&self, lint: &str) -> LintLevel {
+        self.lints.get(lint).copied().unwrap_or(self.warnings)
+    }
+}
+
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
+
+impl CompileWarning {
+    // Denied lints are reported as errors
+    pub fn to_diagnostic(&self, level: LintLevel) -> Diagnostic<()> {
+        let (severity, note) = match level {
+            LintLevel::Deny => (Severity::Error, format!("`{}` is denied", self.lint())),
+            _ => (Severity::Warning, format!("`-A {}` allows it", self.lint())),
+        };
+        Diagnostic::new(severity)
+            .with_code(self.code())
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), self.span().start..self.span().end).with_message(self.label())])
+            .with_notes(vec![note])
+    }
+}
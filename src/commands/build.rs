@@ -1,4 +1,5 @@
-use sbpf_assembler::assemble;
+use sbpf_assembler::{assemble, AssembleOptions};
+use sbpf_assembler::message_format::MessageFormat;
 
 use ed25519_dalek::SigningKey;
 use rand::rngs::OsRng;
@@ -9,7 +10,7 @@ use std::path::Path;
 use std::time::Instant;
 use std::fs::create_dir_all;
 
-pub fn build() -> Result<()> {
+pub fn build(options: AssembleOptions) -> Result<()> {
     // Set src/out directory
     let src = "src";
     let deploy = "deploy";
@@ -17,9 +18,12 @@ pub fn build() -> Result<()> {
     // Create necessary directories
     create_dir_all(deploy)?;
 
+    // stdout only carries diagnostics with json output
+    let human = options.message_format == MessageFormat::Human;
+
     // Function to compile assembly
-    fn compile_assembly(src: &str, deploy: &str) -> Result<()> {
-        assemble(src, deploy)
+    fn compile_assembly(src: &str, deploy: &str, options: &AssembleOptions) -> Result<()> {
+        assemble(src, deploy, options)
     }
 
     // Function to check if keypair file exists.
@@ -65,15 +69,19 @@ pub fn build() -> Result<()> {
             if let Some(subdir) = path.file_name().and_then(|name| name.to_str()) {
                 let asm_file = format!("{}/{}/{}.s", src, subdir, subdir);
                 if Path::new(&asm_file).exists() {
-                    println!("⚡️ Building \"{}\"", subdir);
+                    if human {
+                        println!("⚡️ Building \"{}\"", subdir);
+                    }
                     let start = Instant::now();
-                    compile_assembly(&asm_file, deploy)?;
+                    compile_assembly(&asm_file, deploy, &options)?;
                     let duration = start.elapsed();
-                    println!(
-                        "✅ \"{}\" built successfully in {}ms!",
-                        subdir,
-                        duration.as_micros() as f64 / 1000.0
-                    );
+                    if human {
+                        println!(
+                            "✅ \"{}\" built successfully in {}ms!",
+                            subdir,
+                            duration.as_micros() as f64 / 1000.0
+                        );
+                    }
                 }
             }
         }
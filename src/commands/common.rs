@@ -9,11 +9,12 @@ pub struct SolanaConfig {
 pub const PROGRAM: &str = r#".globl entrypoint
 entrypoint:
   lddw r1, message
-  lddw r2, 14
+  lddw r2, MSG_LEN
   call sol_log_
   exit
 .rodata
   message: .ascii "Hello, Solana!"
+  .equ MSG_LEN, . - message
 "#;
 
 pub const DEFAULT_LINKER: &str = r#"PHDRS
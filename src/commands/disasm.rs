@@ -0,0 +1,14 @@
+use sbpf_assembler::disasm::disassemble;
+
+use anyhow::{Error, Result};
+
+// Prints the program as assembly, or writes it to `output`
+pub fn disasm(file: &str, output: Option<&str>) -> Result<(), Error> {
+    let bytes = std::fs::read(file).map_err(|e| Error::msg(format!("Can't read {}: {}", file, e)))?;
+    let source = disassemble(&bytes).map_err(|e| Error::msg(format!("Can't disassemble {}: {}", file, e)))?;
+    match output {
+        Some(output) => std::fs::write(output, source)?,
+        None => print!("{}", source),
+    }
+    Ok(())
+}
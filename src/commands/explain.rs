@@ -0,0 +1,11 @@
+use anyhow::{Error, Result};
+
+pub fn explain(code: &str) -> Result<(), Error> {
+    match sbpf_assembler::explain::explain(code) {
+        Some(explanation) => {
+            print!("{}", explanation);
+            Ok(())
+        }
+        None => Err(Error::msg(format!("'{}' is not a known error or warning code", code))),
+    }
+}
@@ -0,0 +1,38 @@
+use sbpf_assembler::format_file;
+
+use anyhow::{Error, Result};
+use std::path::Path;
+
+// Formats the given files, or every program under src/ like `build` finds them
+pub fn fmt(files: &[String], check: bool) -> Result<(), Error> {
+    let mut files = files.to_vec();
+    if files.is_empty() {
+        for entry in Path::new("src").read_dir()? {
+            let path = entry?.path();
+            if let Some(subdir) = path.file_name().and_then(|name| name.to_str()) {
+                let asm_file = format!("src/{}/{}.s", subdir, subdir);
+                if Path::new(&asm_file).exists() {
+                    files.push(asm_file);
+                }
+            }
+        }
+        files.sort();
+    }
+
+    let mut unformatted = 0;
+    for file in &files {
+        if format_file(file, check)? {
+            if check {
+                println!("{} is not formatted", file);
+            } else {
+                println!("Formatted {}", file);
+            }
+            unformatted += 1;
+        }
+    }
+    if check && unformatted > 0 {
+        let plural = if unformatted == 1 { "" } else { "s" };
+        return Err(Error::msg(format!("{} file{} would be reformatted, run `sbpf fmt`", unformatted, plural)));
+    }
+    Ok(())
+}
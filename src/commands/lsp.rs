@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{Error, Result};
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle, Severity};
+use sbpf_assembler::analysis::{Analysis, CompletionKind, SymbolKind};
+use sbpf_assembler::SbpfVersion;
+use serde_json::{json, Value};
+
+// A language server over stdio. Documents are reanalyzed on every change,
+// files are small enough that incremental parsing isn't worth it
+pub fn lsp(version: SbpfVersion) -> Result<(), Error> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut documents: HashMap<String, Document> = HashMap::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "sbpf", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => Value::Null,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                // full sync, the last change holds the whole text
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str()),
+                };
+                if let Some(text) = text {
+                    let document = Document::new(text, version);
+                    publish_diagnostics(&mut output, &uri, document.diagnostics(&uri))?;
+                    documents.insert(uri, document);
+                }
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish_diagnostics(&mut output, &uri, Vec::new())?;
+                continue;
+            }
+            "textDocument/hover"
+            | "textDocument/definition"
+            | "textDocument/references"
+            | "textDocument/completion"
+            | "textDocument/documentSymbol" => match documents.get(&uri) {
+                // a bug in a query shouldn't take the session down with it
+                Some(document) => panic::catch_unwind(AssertUnwindSafe(|| document.request(method, &uri, params)))
+                    .unwrap_or(Value::Null),
+                None => Value::Null,
+            },
+            // notifications we don't handle are dropped
+            _ if message.get("id").is_none() => continue,
+            _ => {
+                let error = json!({ "code": -32601, "message": format!("unsupported method '{}'", method) });
+                write_message(&mut output, &json!({ "jsonrpc": "2.0", "id": message["id"], "error": error }))?;
+                continue;
+            }
+        };
+        write_message(&mut output, &json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))?;
+    }
+    Ok(())
+}
+
+struct Document {
+    text: String,
+    // the panic message if analyzing the text hit a bug in the assembler
+    analysis: Result<Analysis, String>,
+}
+
+impl Document {
+    fn new(text: &str, version: SbpfVersion) -> Self {
+        let analysis = panic::catch_unwind(|| Analysis::new(text, version)).map_err(|payload| {
+            match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+            }
+        });
+        Self { text: text.to_string(), analysis }
+    }
+
+    fn diagnostics(&self, uri: &str) -> Vec<Value> {
+        match &self.analysis {
+            Ok(analysis) => analysis.diagnostics().iter()
+                .map(|diagnostic| self.diagnostic(uri, diagnostic))
+                .collect(),
+            Err(message) => vec![json!({
+                "range": self.range(&(0..0)),
+                "severity": 1,
+                "source": "sbpf",
+                "message": format!("internal error while analyzing this file, please report it as a bug: {}", message),
+            })],
+        }
+    }
+
+    fn request(&self, method: &str, uri: &str, params: &Value) -> Value {
+        let Ok(analysis) = &self.analysis else { return Value::Null };
+        let offset = self.offset(&params["position"]);
+        match method {
+            "textDocument/hover" => match analysis.hover(offset) {
+                Some(text) => json!({ "contents": { "kind": "markdown", "value": text } }),
+                None => Value::Null,
+            },
+            "textDocument/definition" => match analysis.definition(offset) {
+                Some(span) => self.location(uri, &span),
+                None => Value::Null,
+            },
+            "textDocument/references" => {
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                analysis.references(offset, include_declaration).iter()
+                    .map(|span| self.location(uri, span))
+                    .collect()
+            }
+            "textDocument/completion" => {
+                let (replace, completions) = analysis.completions(offset);
+                completions.iter()
+                    .map(|completion| json!({
+                        "label": completion.label,
+                        "kind": completion_kind(completion.kind),
+                        "detail": completion.detail,
+                        "textEdit": { "range": self.range(&replace), "newText": completion.label },
+                    }))
+                    .collect()
+            }
+            "textDocument/documentSymbol" => analysis.symbols().iter()
+                .filter_map(|symbol| {
+                    let kind = match symbol.kind {
+                        SymbolKind::Label => 12,
+                        SymbolKind::Data => 13,
+                        SymbolKind::Constant => 14,
+                        // declared here, defined by the runtime
+                        SymbolKind::Extern => return None,
+                    };
+                    Some(json!({
+                        "name": symbol.name,
+                        "detail": symbol.detail,
+                        "kind": kind,
+                        "range": self.range(&symbol.span),
+                        "selectionRange": self.range(&symbol.span),
+                    }))
+                })
+                .collect(),
+            _ => Value::Null,
+        }
+    }
+
+    // Label messages and notes are folded into the message, other labels
+    // become related information
+    fn diagnostic(&self, uri: &str, diagnostic: &Diagnostic<()>) -> Value {
+        let primary = diagnostic.labels.iter().find(|label| label.style == LabelStyle::Primary);
+        let mut message = diagnostic.message.clone();
+        if let Some(label) = primary.filter(|label| !label.message.is_empty()) {
+            message += &format!("\n{}", label.message);
+        }
+        for note in &diagnostic.notes {
+            message += &format!("\n{}", note);
+        }
+        let related: Vec<_> = diagnostic.labels.iter()
+            .filter(|label| label.style == LabelStyle::Secondary)
+            .map(|label| json!({ "location": self.location(uri, &label.range), "message": label.message }))
+            .collect();
+        let severity = match diagnostic.severity {
+            Severity::Bug | Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Note => 3,
+            Severity::Help => 4,
+        };
+        json!({
+            "range": self.range(&primary.map_or(0..0, |label| label.range.clone())),
+            "severity": severity,
+            "code": diagnostic.code,
+            "source": "sbpf",
+            "message": message,
+            "relatedInformation": related,
+        })
+    }
+
+    fn location(&self, uri: &str, span: &Range<usize>) -> Value {
+        json!({ "uri": uri, "range": self.range(span) })
+    }
+
+    fn range(&self, span: &Range<usize>) -> Value {
+        json!({ "start": self.position(span.start), "end": self.position(span.end) })
+    }
+
+    // LSP columns count UTF-16 code units
+    fn position(&self, offset: usize) -> Value {
+        let offset = offset.min(self.text.len());
+        let line_start = self.text[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+        let line = self.text[..line_start].matches('\n').count();
+        let character = self.text[line_start..offset].encode_utf16().count();
+        json!({ "line": line, "character": character })
+    }
+
+    fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let character = position["character"].as_u64().unwrap_or_default() as usize;
+        let mut line_start = 0;
+        for _ in 0..line {
+            match self.text[line_start..].find('\n') {
+                Some(idx) => line_start += idx + 1,
+                None => return self.text.len(),
+            }
+        }
+        let mut units = 0;
+        for (idx, c) in self.text[line_start..].char_indices() {
+            if units >= character || c == '\n' {
+                return line_start + idx;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
+fn completion_kind(kind: CompletionKind) -> u8 {
+    match kind {
+        CompletionKind::Instruction | CompletionKind::Directive => 14,
+        CompletionKind::Register => 6,
+        CompletionKind::Syscall | CompletionKind::Symbol(SymbolKind::Extern) => 3,
+        CompletionKind::Symbol(SymbolKind::Label) => 18,
+        CompletionKind::Symbol(SymbolKind::Data) => 5,
+        CompletionKind::Symbol(SymbolKind::Constant) => 21,
+    }
+}
+
+fn publish_diagnostics(output: &mut impl Write, uri: &str, diagnostics: Vec<Value>) -> Result<(), Error> {
+    write_message(output, &json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }))
+}
+
+// Messages are framed by a Content-Length header, None once the client
+// closes the stream
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| Error::msg("message without a Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<(), Error> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
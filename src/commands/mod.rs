@@ -13,4 +13,16 @@ pub use test::*;
 pub mod clean;
 pub use clean::*;
 
+pub mod explain;
+pub use explain::*;
+
+pub mod lsp;
+pub use lsp::*;
+
+pub mod fmt;
+pub use fmt::*;
+
+pub mod disasm;
+pub use disasm::*;
+
 pub mod common;
@@ -1,32 +1,16 @@
 use anyhow::{Error, Result};
-use std::{fs, io, path::Path, process::Command};
+use std::{fs, io, path::{Path, PathBuf}, process::Command};
+use sbpf_assembler::elf::ElfFile;
+use sbpf_assembler::{AssembleOptions, SbpfVersion};
 
-pub fn test() -> Result<(), Error> {
+pub fn test(options: AssembleOptions) -> Result<(), Error> {
     println!("🧪 Running tests");
 
     let deploy_dir = Path::new("deploy");
 
-    fn has_so_files(dir: &Path) -> bool {
-        if dir.exists() && dir.is_dir() {
-            match fs::read_dir(dir) {
-                Ok(entries) => entries.filter_map(Result::ok).any(|entry| {
-                    entry
-                        .path()
-                        .extension()
-                        .and_then(|ext| ext.to_str())
-                        .map(|ext| ext == "so")
-                        .unwrap_or(false)
-                }),
-                Err(_) => false,
-            }
-        } else {
-            false
-        }
-    }
-
-    if !has_so_files(deploy_dir) {
+    if !built_for(deploy_dir, options.version)? {
         println!("🔄 No .so files found in 'deploy' directory. Running build...");
-        crate::commands::build::build()?;
+        crate::commands::build::build(options)?;
     }
 
     let has_cargo = Path::new("Cargo.toml").exists();
@@ -43,10 +27,7 @@ pub fn test() -> Result<(), Error> {
 
             if !output.success() {
                 eprintln!("Failed to run Rust tests");
-                return Err(Error::new(io::Error::new(
-                    io::ErrorKind::Other,
-                    "❌ Rust tests failed",
-                )));
+                return Err(Error::new(io::Error::other("❌ Rust tests failed")));
             }
         }
         (false, true) => {
@@ -56,10 +37,7 @@ pub fn test() -> Result<(), Error> {
 
             if !status.success() {
                 eprintln!("Failed to run tests");
-                return Err(Error::new(io::Error::new(
-                    io::ErrorKind::Other,
-                    "❌ Test failed",
-                )));
+                return Err(Error::new(io::Error::other("❌ Test failed")));
             }
         }
         (false, false) => {
@@ -73,3 +51,50 @@ pub fn test() -> Result<(), Error> {
     println!("✅ Tests completed successfully!");
     Ok(())
 }
+
+// Whether there are programs to test, an error if any was built for another
+// version, testing it would pass or fail for the wrong reasons
+fn built_for(deploy_dir: &Path, version: SbpfVersion) -> Result<bool, Error> {
+    let programs: Vec<PathBuf> = match fs::read_dir(deploy_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("so"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    for program in &programs {
+        let bytes = fs::read(program)?;
+        let built = ElfFile::parse(&bytes).ok().and_then(|elf| SbpfVersion::from_e_flags(elf.e_flags));
+        if built != Some(version) {
+            let built = built.map_or("an unknown version".to_string(), |built| built.to_string());
+            return Err(Error::msg(format!(
+                "❌ {} was built for {}, not {}. Run `sbpf build --arch {}` first",
+                program.display(), built, version, version
+            )));
+        }
+    }
+    Ok(!programs.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_programs_are_not_tested() {
+        let dir = std::env::temp_dir().join(format!("sbpf_test_built_for_{}", std::process::id()));
+        let deploy = dir.join("deploy");
+        fs::create_dir_all(&deploy).unwrap();
+        assert!(!built_for(&deploy, SbpfVersion::V3).unwrap());
+
+        let source = dir.join("program.s");
+        fs::write(&source, ".globl entrypoint\nentrypoint:\n  exit\n").unwrap();
+        let options = AssembleOptions { version: SbpfVersion::V0, ..AssembleOptions::default() };
+        sbpf_assembler::assemble(source.to_str().unwrap(), deploy.to_str().unwrap(), &options).unwrap();
+        assert!(built_for(&deploy, SbpfVersion::V0).unwrap());
+        let error = built_for(&deploy, SbpfVersion::V3).unwrap_err().to_string();
+        assert!(error.contains("was built for v0, not v3"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
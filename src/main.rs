@@ -1,7 +1,10 @@
 pub mod commands;
 use anyhow::Error;
-use clap::{Args, Parser, Subcommand};
-use commands::{build, clean, deploy, init, test};
+use clap::{Arg, ArgAction, ArgMatches, Args, Command, FromArgMatches, Parser, Subcommand};
+use commands::{build, clean, deploy, disasm, explain, fmt, init, lsp, test};
+use sbpf_assembler::{AssembleOptions, SbpfVersion};
+use sbpf_assembler::message_format::MessageFormat;
+use sbpf_assembler::warnings::{LintLevel, LintPolicy};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -16,15 +19,23 @@ enum Commands {
     #[command(about = "Create a new project scaffold")]
     Init(InitArgs),
     #[command(about = "Compile into a Solana program executable")]
-    Build,
+    Build(BuildArgs),
     #[command(about = "Build and deploy the program")]
     Deploy(DeployArgs),
     #[command(about = "Test deployed program")]
-    Test,
+    Test(BuildArgs),
     #[command(about = "Build, deploy and test a program")]
-    E2E(DeployArgs),
+    E2E(E2EArgs),
     #[command(about = "Clean up build and deploy artifacts")]
     Clean,
+    #[command(about = "Explain an error or warning code, e.g. E0007")]
+    Explain { code: String },
+    #[command(about = "Run the language server over stdio")]
+    Lsp(LspArgs),
+    #[command(about = "Format assembly sources")]
+    Fmt(FmtArgs),
+    #[command(about = "Disassemble a program back into assembly")]
+    Disasm(DisasmArgs),
 }
 
 #[derive(Args)]
@@ -38,26 +49,173 @@ pub struct InitArgs {
     ts_tests: bool,
 }
 
+#[derive(Args)]
+struct BuildArgs {
+    #[arg(
+        long,
+        default_value_t = SbpfVersion::default(),
+        help = "Target SBPF version (v0, v1, v2, v3)"
+    )]
+    arch: SbpfVersion,
+    #[command(flatten)]
+    lints: LintArgs,
+    #[arg(
+        long,
+        default_value_t = MessageFormat::default(),
+        help = "Diagnostics output (human, json)"
+    )]
+    message_format: MessageFormat,
+}
+
+impl BuildArgs {
+    fn options(&self) -> Result<AssembleOptions, Error> {
+        Ok(AssembleOptions { version: self.arch, lints: self.lints.policy()?, message_format: self.message_format })
+    }
+}
+
+// `-W`, `-D` and `-A` in the order they were given on the command line, the
+// last one for a lint wins. Derived args would group them by flag
+struct LintArgs {
+    levels: Vec<(LintLevel, String)>,
+}
+
+impl LintArgs {
+    const FLAGS: [(&'static str, LintLevel); 3] = [("warn", LintLevel::Warn), ("deny", LintLevel::Deny), ("allow", LintLevel::Allow)];
+
+    fn policy(&self) -> Result<LintPolicy, Error> {
+        let mut lints = LintPolicy::default();
+        for (level, name) in &self.levels {
+            lints.set(name, *level).map_err(Error::msg)?;
+        }
+        Ok(lints)
+    }
+}
+
+impl Args for LintArgs {
+    fn augment_args(cmd: Command) -> Command {
+        let flag = |id: &'static str, short: char, help: &'static str| {
+            Arg::new(id).short(short).value_name("LINT").action(ArgAction::Append).help(help)
+        };
+        cmd.arg(flag("warn", 'W', "Warn on a lint, `warnings` for all of them"))
+            .arg(flag("deny", 'D', "Fail the build on a lint, e.g. -D warnings"))
+            .arg(flag("allow", 'A', "Allow a lint"))
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        Self::augment_args(cmd)
+    }
+}
+
+impl FromArgMatches for LintArgs {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut levels = Vec::new();
+        for (id, level) in Self::FLAGS {
+            if let (Some(indices), Some(names)) = (matches.indices_of(id), matches.get_many::<String>(id)) {
+                levels.extend(indices.zip(names).map(|(idx, name)| (idx, level, name.clone())));
+            }
+        }
+        levels.sort_by_key(|(idx, _, _)| *idx);
+        Ok(Self { levels: levels.into_iter().map(|(_, level, name)| (level, name)).collect() })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct LspArgs {
+    #[arg(
+        long,
+        default_value_t = SbpfVersion::default(),
+        help = "Target SBPF version (v0, v1, v2, v3)"
+    )]
+    arch: SbpfVersion,
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    #[arg(help = "Files to format, every program under src/ by default")]
+    files: Vec<String>,
+    #[arg(long, help = "Fail instead of writing if any file isn't formatted")]
+    check: bool,
+}
+
+#[derive(Args)]
+struct DisasmArgs {
+    #[arg(help = "The program, e.g. deploy/hello.so")]
+    file: String,
+    #[arg(short, long, help = "Write the assembly to a file instead of stdout")]
+    output: Option<String>,
+}
+
 #[derive(Args)]
 struct DeployArgs {
     name: Option<String>,
     url: Option<String>,
 }
 
+#[derive(Args)]
+struct E2EArgs {
+    #[command(flatten)]
+    deploy: DeployArgs,
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
 fn main() -> Result<(), Error> {
     let cli = Cli::parse();
 
     match &cli.command {
         Commands::Init(args) => init(args.name.clone(), args.ts_tests),
-        Commands::Build => build(),
+        Commands::Build(args) => build(args.options()?),
         Commands::Deploy(args) => deploy(args.name.clone(), args.url.clone()),
-        Commands::Test => test(),
+        // builds only if nothing was built yet
+        Commands::Test(args) => test(args.options()?),
         // use arg to specify if use light build
         Commands::E2E(args) => {
-            build()?;
-            deploy(args.name.clone(), args.url.clone())?;
-            test()
+            let options = args.build.options()?;
+            build(options.clone())?;
+            deploy(args.deploy.name.clone(), args.deploy.url.clone())?;
+            test(options)
         }
         Commands::Clean => clean(),
+        Commands::Explain { code } => explain(code),
+        Commands::Lsp(args) => lsp(args.arch),
+        Commands::Fmt(args) => fmt(&args.files, args.check),
+        Commands::Disasm(args) => disasm(&args.file, args.output.as_deref()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_level(args: &[&str], lint: &str) -> LintLevel {
+        let cli = Cli::try_parse_from([&["sbpf", "build"], args].concat()).unwrap();
+        let Commands::Build(build) = cli.command else { unreachable!() };
+        build.lints.policy().unwrap().level(lint)
+    }
+
+    #[test]
+    fn test_lint_flags_apply_in_order() {
+        assert_eq!(lint_level(&["-D", "warnings", "-W", "unused_labels"], "unused_labels"), LintLevel::Warn);
+        assert_eq!(lint_level(&["-D", "warnings", "-W", "unused_labels"], "missing_exit"), LintLevel::Deny);
+        assert_eq!(lint_level(&["-D", "unused_labels", "-A", "unused_labels"], "unused_labels"), LintLevel::Allow);
+        assert_eq!(lint_level(&["-A", "unused_labels", "-D", "unused_labels"], "unused_labels"), LintLevel::Deny);
+    }
+
+    #[test]
+    fn test_e2e_and_test_build_for_the_target() {
+        let cli = Cli::try_parse_from(["sbpf", "e2e", "program", "--arch", "v2", "-D", "warnings"]).unwrap();
+        let Commands::E2E(args) = cli.command else { unreachable!() };
+        assert_eq!(args.deploy.name.as_deref(), Some("program"));
+        let options = args.build.options().unwrap();
+        assert_eq!(options.version, SbpfVersion::V2);
+        assert_eq!(options.lints.level("unused_labels"), LintLevel::Deny);
+        let cli = Cli::try_parse_from(["sbpf", "test", "--arch", "v3"]).unwrap();
+        let Commands::Test(args) = cli.command else { unreachable!() };
+        assert_eq!(args.options().unwrap().version, SbpfVersion::V3);
     }
 }
@@ -70,7 +70,7 @@ impl TestEnv {
 
 /// Run a command and return the output, panicking on failure
 pub fn run_command(cmd: &mut Command, operation_name: &str) -> Output {
-    let output = cmd.output().expect(&format!("Failed to execute {}", operation_name));
+    let output = cmd.output().unwrap_or_else(|_| panic!("Failed to execute {}", operation_name));
     
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -162,6 +162,6 @@ pub fn run_tests(env: &TestEnv) {
 /// Update the project's assembly file content
 pub fn update_assembly_file(env: &TestEnv, project_name: &str, content: &str) {
     let assembly_path = env.project_dir.join(format!("src/{}/{}.s", project_name, project_name));
-    fs::write(&assembly_path, content).expect(&format!("Failed to write new {}.s content", project_name));
+    fs::write(&assembly_path, content).unwrap_or_else(|_| panic!("Failed to write new {}.s content", project_name));
     println!("✅ Updated {}.s with specified content", project_name);
 }